@@ -0,0 +1,236 @@
+//! Adaptive Huffman coding (FGK): encoder and decoder update the same tree after every
+//! symbol, so no statistics pass and no tree header are needed.
+//!
+//! The tree starts with the NYT ("not yet transmitted") leaf alone. A new symbol is sent as
+//! the code of NYT followed by its 8 bits, then NYT splits into a new NYT and the symbol leaf.
+
+use bitvec_util::*;
+use error::{Error, Offset};
+use std::collections::VecDeque;
+
+const NONE: usize = usize::MAX;
+/// 256 symbol leaves, the NYT leaf and the internal nodes joining them.
+const NODES: usize = 2 * 257 - 1;
+const ROOT: usize = NODES - 1;
+
+#[derive(Clone, Copy)]
+enum Kind {
+    Nyt,
+    Leaf(u8),
+    Internal(usize, usize),
+}
+
+#[derive(Clone, Copy)]
+struct Slot {
+    weight: u64,
+    kind: Kind,
+}
+
+/// Nodes are indexed by their FGK number: weights never decrease with the number (sibling
+/// property), the root has the highest one and NYT the lowest.
+pub struct FgkTree {
+    slots: Vec<Slot>,
+    parents: Vec<usize>,
+    leaves: [usize; 256],
+    nyt: usize,
+}
+
+impl FgkTree {
+    pub fn new() -> FgkTree {
+        FgkTree {
+            slots: vec![Slot { weight: 0, kind: Kind::Nyt }; NODES],
+            parents: vec![NONE; NODES],
+            leaves: [NONE; 256],
+            nyt: ROOT,
+        }
+    }
+
+    pub fn encode(&mut self, symbol: u8, output: &mut VecDeque<bool>) {
+        let leaf = self.leaves[symbol as usize];
+        let start = output.len();
+        let mut node = if leaf == NONE { self.nyt } else { leaf };
+        while self.parents[node] != NONE {
+            let parent = self.parents[node];
+            let is_right = match self.slots[parent].kind {
+                Kind::Internal(_, right) => right == node,
+                _ => unreachable!(),
+            };
+            output.push_back(is_right);
+            node = parent;
+        }
+        // the path was pushed from the leaf up
+        let end = output.len();
+        for i in 0..(end - start) / 2 {
+            output.swap(start + i, end - 1 - i);
+        }
+        if leaf == NONE {
+            for i in (0..8).rev() {
+                output.push_back(symbol >> i & 1 == 1);
+            }
+        }
+        self.update(symbol);
+    }
+
+    /// Decodes the next symbol, or returns `None` if there are no more bits.
+    pub fn decode(&mut self, iter: &mut BitReader) -> Result<Option<u8>, Error> {
+        if iter.remaining() == 0 {
+            return Ok(None);
+        }
+        let mut node = ROOT;
+        loop {
+            match self.slots[node].kind {
+                Kind::Internal(left, right) => {
+                    node = match iter.next() {
+                        Some(bit) => if bit { right } else { left },
+                        None => {
+                            return Err(Error::UnexpectedEof { offset: Offset::Bit(iter.offset()) })
+                        }
+                    };
+                }
+                Kind::Leaf(symbol) => {
+                    self.update(symbol);
+                    return Ok(Some(symbol));
+                }
+                Kind::Nyt => {
+                    let position = iter.offset();
+                    let symbol = read_bits(iter, 8)? as u8;
+                    if self.leaves[symbol as usize] != NONE {
+                        return Err(Error::InvalidTree { offset: Offset::Bit(position) });
+                    }
+                    self.update(symbol);
+                    return Ok(Some(symbol));
+                }
+            }
+        }
+    }
+
+    fn update(&mut self, symbol: u8) {
+        let mut node = self.leaves[symbol as usize];
+        if node == NONE {
+            // NYT becomes an internal node over a new NYT (left) and the new leaf (right)
+            let (internal, nyt) = (self.nyt, self.nyt - 2);
+            node = self.nyt - 1;
+            self.slots[internal].kind = Kind::Internal(nyt, node);
+            self.slots[node] = Slot { weight: 0, kind: Kind::Leaf(symbol) };
+            self.slots[nyt] = Slot { weight: 0, kind: Kind::Nyt };
+            self.parents[node] = internal;
+            self.parents[nyt] = internal;
+            self.leaves[symbol as usize] = node;
+            self.nyt = nyt;
+        }
+
+        while node != NONE {
+            // move the node to the highest number of its weight before incrementing it
+            let weight = self.slots[node].weight;
+            let mut leader = node;
+            while leader < ROOT && self.slots[leader + 1].weight == weight {
+                leader += 1;
+            }
+            if leader != node && leader != self.parents[node] {
+                self.swap(node, leader);
+                node = leader;
+            }
+            self.slots[node].weight += 1;
+            node = self.parents[node];
+        }
+    }
+
+    /// Exchanges the subtrees numbered `a` and `b`; parents stay attached to the numbers.
+    fn swap(&mut self, a: usize, b: usize) {
+        self.slots.swap(a, b);
+        for &node in &[a, b] {
+            match self.slots[node].kind {
+                Kind::Internal(left, right) => {
+                    self.parents[left] = node;
+                    self.parents[right] = node;
+                }
+                Kind::Leaf(symbol) => self.leaves[symbol as usize] = node,
+                Kind::Nyt => self.nyt = node,
+            }
+        }
+    }
+}
+
+/// Bytes of the adaptive code of the input, produced as the input is read. As for the other
+/// bit streams, the last byte counts the padding bits of the one before it.
+pub struct AdaptiveHuffmanCodingIter<I> {
+    iter: I,
+    tree: FgkTree,
+    bits: VecDeque<bool>,
+    padding: Option<u8>,
+    done: bool,
+}
+
+impl<I> Iterator for AdaptiveHuffmanCodingIter<I>
+    where I: Iterator<Item = u8>
+{
+    type Item = u8;
+    fn next(&mut self) -> Option<u8> {
+        while self.bits.len() < 8 && self.padding.is_none() {
+            match self.iter.next() {
+                Some(symbol) => self.tree.encode(symbol, &mut self.bits),
+                None => {
+                    self.padding = Some(((8 - self.bits.len() % 8) % 8) as u8);
+                }
+            }
+        }
+        if self.bits.is_empty() {
+            // nothing at all is written for an empty input
+            if self.done || self.tree.nyt == ROOT {
+                return None;
+            }
+            self.done = true;
+            return self.padding;
+        }
+        let mut byte = 0;
+        for i in 0..8 {
+            if self.bits.pop_front() == Some(true) {
+                byte |= 1 << (7 - i);
+            }
+        }
+        Some(byte)
+    }
+}
+
+pub fn adaptive_huffman_coding<I>(iter: I) -> AdaptiveHuffmanCodingIter<I>
+    where I: Iterator<Item = u8>
+{
+    AdaptiveHuffmanCodingIter {
+        iter,
+        tree: FgkTree::new(),
+        bits: VecDeque::new(),
+        padding: None,
+        done: false,
+    }
+}
+
+pub fn adaptive_huffman_decoding(iter: &mut BitReader) -> Result<Vec<u8>, Error> {
+    let mut tree = FgkTree::new();
+    let mut output = Vec::new();
+    while let Some(symbol) = tree.decode(iter)? {
+        output.push(symbol);
+    }
+    Ok(output)
+}
+
+#[test]
+fn adaptive_huffman_testing() {
+    let input = "abracadabra abracadabra abracadabra".as_bytes().to_vec();
+    let coded: Vec<u8> = adaptive_huffman_coding(input.iter().cloned()).collect();
+    assert_eq!(coded, serialize_bit_vec(&deserialize_bit_vec(&coded).unwrap()));
+    let bits = deserialize_bit_vec(&coded).unwrap();
+    assert_eq!(input, adaptive_huffman_decoding(&mut BitReader::new(&bits)).unwrap());
+
+    // every byte value, with skewed frequencies
+    let mut input = Vec::new();
+    for i in 0..4000u32 {
+        input.push((i * i % 251) as u8);
+        input.push((i % 7) as u8);
+    }
+    input.extend(0..=255);
+    let coded: Vec<u8> = adaptive_huffman_coding(input.iter().cloned()).collect();
+    let bits = deserialize_bit_vec(&coded).unwrap();
+    assert_eq!(input, adaptive_huffman_decoding(&mut BitReader::new(&bits)).unwrap());
+
+    assert_eq!(adaptive_huffman_coding(Vec::new().into_iter()).next(), None);
+}
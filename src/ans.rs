@@ -0,0 +1,426 @@
+//! Asymmetric numeral systems: symbol frequencies are normalized to a power of two, the table
+//! size, then coded with rANS (arithmetic on a 64 bit state) or tANS (finite state machine
+//! tables, as in FSE).
+//!
+//! Both encoders run over the symbols backwards: the caller puts them last first, and the
+//! decoders get them first first.
+
+use bit_vec::BitVec;
+use bitvec_util::*;
+use error::{Error, Offset};
+use huffman::Symbol;
+use std::collections::BTreeMap;
+
+pub const MIN_TABLE_LOG: u32 = 5;
+pub const MAX_TABLE_LOG: u32 = 20;
+
+/// rANS state bounds: the state stays in `[RANS_LOW, RANS_LOW << 32)`.
+const RANS_LOW: u64 = 1 << 31;
+
+/// A table size for `distinct` symbols: a few slots per symbol, at least 2^11.
+pub fn table_log(distinct: usize) -> u32 {
+    assert!(distinct <= 1 << MAX_TABLE_LOG);
+    let need = usize::BITS - (distinct.max(1) - 1).leading_zeros();
+    (need + 2).clamp(11, MAX_TABLE_LOG).max(need)
+}
+
+/// Frequencies of the symbols that occur, summing to `1 << table_log`.
+pub struct Frequencies<T> {
+    pub table_log: u32,
+    symbols: Vec<T>,
+    frequencies: Vec<u32>,
+    starts: Vec<u32>,
+    indexes: BTreeMap<T, usize>,
+}
+
+impl<T: Symbol> Frequencies<T> {
+    /// Scales `statistics` to the table size; every symbol keeps at least one slot. A lone
+    /// symbol gets a neighbor, since it would cost no bits at all.
+    pub fn from_statistics(statistics: &BTreeMap<T, u64>, table_log: u32) -> Frequencies<T> {
+        let size = 1u64 << table_log;
+        assert!((MIN_TABLE_LOG..=MAX_TABLE_LOG).contains(&table_log));
+        assert!(!statistics.is_empty() && statistics.len() as u64 <= size);
+        let mut statistics = statistics.clone();
+        if statistics.len() == 1 {
+            let symbol = *statistics.keys().next().unwrap();
+            statistics.insert(symbol.neighbor(), 0);
+        }
+        let sum: u64 = statistics.values().sum();
+        let mut frequencies: Vec<(T, u64)> = statistics.iter()
+            .map(|(&symbol, &count)| {
+                (symbol, ((count as u128 * size as u128 / sum as u128) as u64).max(1))
+            })
+            .collect();
+
+        // give the difference to (or take it from) the most frequent symbols
+        let mut order: Vec<usize> = (0..frequencies.len()).collect();
+        order.sort_by_key(|&i| (std::cmp::Reverse(frequencies[i].1), i));
+        let mut total: u64 = frequencies.iter().map(|&(_, frequency)| frequency).sum();
+        if total < size {
+            frequencies[order[0]].1 += size - total;
+        }
+        for &i in &order {
+            if total <= size {
+                break;
+            }
+            let taken = (frequencies[i].1 - 1).min(total - size);
+            frequencies[i].1 -= taken;
+            total -= taken;
+        }
+        Frequencies::from_frequencies(table_log,
+                                      frequencies.into_iter()
+                                          .map(|(symbol, frequency)| (symbol, frequency as u32)))
+    }
+
+    fn from_frequencies<I>(table_log: u32, iter: I) -> Frequencies<T>
+        where I: Iterator<Item = (T, u32)>
+    {
+        let mut frequencies = Frequencies {
+            table_log,
+            symbols: Vec::new(),
+            frequencies: Vec::new(),
+            starts: Vec::new(),
+            indexes: BTreeMap::new(),
+        };
+        let mut start = 0;
+        for (symbol, frequency) in iter {
+            frequencies.indexes.insert(symbol, frequencies.symbols.len());
+            frequencies.symbols.push(symbol);
+            frequencies.frequencies.push(frequency);
+            frequencies.starts.push(start);
+            start += frequency;
+        }
+        frequencies
+    }
+
+    /// The table log on 5 bits, the symbol count, then each symbol (as the gap from the
+    /// previous one) and its frequency.
+    pub fn encode(&self) -> BitVec {
+        let mut output = BitVec::new();
+        push_bits(&mut output, self.table_log as u64, 5);
+        push_gamma(&mut output, self.symbols.len() as u64);
+        let mut next = 0;
+        for (symbol, &frequency) in self.symbols.iter().zip(&self.frequencies) {
+            push_gamma(&mut output, symbol.index() - next + 1);
+            push_gamma(&mut output, frequency as u64);
+            next = symbol.index() + 1;
+        }
+        output
+    }
+
+    pub fn decode(iter: &mut BitReader) -> Result<Frequencies<T>, Error> {
+        let start = iter.offset();
+        let invalid = Err(Error::InvalidHeader { offset: Offset::Bit(start) });
+        let table_log = read_bits(iter, 5)? as u32;
+        let count = read_gamma(iter)?;
+        if !(MIN_TABLE_LOG..=MAX_TABLE_LOG).contains(&table_log) || count < 2 ||
+           count > 1 << table_log {
+            return invalid;
+        }
+        let mut frequencies = Vec::new();
+        let (mut next, mut total) = (0u64, 0u64);
+        for _ in 0..count {
+            let index = next.checked_add(read_gamma(iter)? - 1);
+            let frequency = read_gamma(iter)?;
+            // compared before adding, so that a huge frequency cannot overflow the total
+            let symbol = match index.and_then(T::from_index) {
+                Some(symbol) if frequency <= (1 << table_log) - total => symbol,
+                _ => return invalid,
+            };
+            total += frequency;
+            frequencies.push((symbol, frequency as u32));
+            next = symbol.index() + 1;
+        }
+        if total != 1 << table_log {
+            return invalid;
+        }
+        Ok(Frequencies::from_frequencies(table_log, frequencies.into_iter()))
+    }
+
+    /// Most symbols that `bits` bits of rANS or tANS code can hold, where `gap` is the table
+    /// size less the largest frequency: a rANS symbol takes at least `gap / 2 size` of the
+    /// state away, and tANS reads a bit at least every `size / gap` symbols.
+    pub fn max_symbols(&self, bits: usize) -> u64 {
+        let size = 1u64 << self.table_log;
+        let gap = size - *self.frequencies.iter().max().unwrap() as u64;
+        (bits as u64 + 64).saturating_mul(2 * size) / gap
+    }
+
+    /// Index of the symbol owning each slot of the table, in symbol order.
+    fn slots(&self) -> Vec<u32> {
+        let mut slots = Vec::with_capacity(1 << self.table_log);
+        for (i, &frequency) in self.frequencies.iter().enumerate() {
+            slots.extend(std::iter::repeat_n(i as u32, frequency as usize));
+        }
+        slots
+    }
+}
+
+pub struct RansEncoder {
+    state: u64,
+    words: Vec<u32>,
+}
+
+impl RansEncoder {
+    pub fn new() -> RansEncoder {
+        RansEncoder {
+            state: RANS_LOW,
+            words: Vec::new(),
+        }
+    }
+
+    pub fn put<T: Symbol>(&mut self, frequencies: &Frequencies<T>, symbol: T) {
+        let i = frequencies.indexes[&symbol];
+        let (frequency, start) = (frequencies.frequencies[i] as u64, frequencies.starts[i] as u64);
+        let table_log = frequencies.table_log;
+        let limit = ((RANS_LOW >> table_log) << 32) * frequency;
+        if self.state >= limit {
+            self.words.push(self.state as u32);
+            self.state >>= 32;
+        }
+        self.state = ((self.state / frequency) << table_log) + self.state % frequency + start;
+    }
+
+    /// The final state, then the words in the order the decoder reads them.
+    pub fn finish(self) -> BitVec {
+        let mut output = BitVec::new();
+        push_bits(&mut output, self.state, 64);
+        for &word in self.words.iter().rev() {
+            push_bits(&mut output, word as u64, 32);
+        }
+        output
+    }
+}
+
+pub struct RansDecoder {
+    state: u64,
+}
+
+impl RansDecoder {
+    pub fn new(iter: &mut BitReader) -> Result<RansDecoder, Error> {
+        Ok(RansDecoder { state: read_bits(iter, 64)? })
+    }
+
+    pub fn get<T: Symbol>(&mut self, table: &RansTable<T>, iter: &mut BitReader)
+                          -> Result<T, Error> {
+        let frequencies = &table.frequencies;
+        let mask = (1 << frequencies.table_log) - 1;
+        let slot = self.state & mask;
+        let i = table.slots[slot as usize] as usize;
+        self.state = frequencies.frequencies[i] as u64 * (self.state >> frequencies.table_log) +
+                     slot - frequencies.starts[i] as u64;
+        if self.state < RANS_LOW {
+            self.state = self.state << 32 | read_bits(iter, 32)?;
+        }
+        Ok(frequencies.symbols[i])
+    }
+}
+
+/// Slot lookup for the rANS decoder.
+pub struct RansTable<T> {
+    frequencies: Frequencies<T>,
+    slots: Vec<u32>,
+}
+
+impl<T: Symbol> RansTable<T> {
+    pub fn new(frequencies: Frequencies<T>) -> RansTable<T> {
+        let slots = frequencies.slots();
+        RansTable { frequencies, slots }
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Transition {
+    symbol: u32,
+    bits: u32,
+    base: u32,
+}
+
+/// tANS tables: the symbols are spread over the states, then state `x` of symbol `s` (with
+/// `frequency(s) <= x < 2 frequency(s)`) is the `x - frequency(s)`-th state holding `s`.
+pub struct TansTable<T> {
+    frequencies: Frequencies<T>,
+    /// By state: the symbol, the bits to read and what to add them to.
+    decode: Vec<Transition>,
+    /// By symbol start plus `x - frequency`: the state, offset by the table size.
+    encode: Vec<u32>,
+}
+
+impl<T: Symbol> TansTable<T> {
+    pub fn new(frequencies: Frequencies<T>) -> TansTable<T> {
+        let size = 1usize << frequencies.table_log;
+        // odd, so that it visits every state
+        let step = (size >> 1) + (size >> 3) + 3;
+        let mut spread = vec![0; size];
+        let mut position = 0;
+        for (i, &frequency) in frequencies.frequencies.iter().enumerate() {
+            for _ in 0..frequency {
+                spread[position] = i as u32;
+                position = (position + step) & (size - 1);
+            }
+        }
+
+        let mut next: Vec<u32> = frequencies.frequencies.clone();
+        let mut decode = Vec::with_capacity(size);
+        let mut encode = vec![0; size];
+        for (state, &i) in spread.iter().enumerate() {
+            let x = next[i as usize];
+            next[i as usize] += 1;
+            let bits = frequencies.table_log - (31 - x.leading_zeros());
+            decode.push(Transition {
+                symbol: i,
+                bits,
+                base: (x << bits) - size as u32,
+            });
+            let index = frequencies.starts[i as usize] + x - frequencies.frequencies[i as usize];
+            encode[index as usize] = (size + state) as u32;
+        }
+        TansTable {
+            frequencies,
+            decode,
+            encode,
+        }
+    }
+}
+
+pub struct TansEncoder {
+    table_log: u32,
+    state: u32,
+    chunks: Vec<(u32, u32)>,
+}
+
+impl TansEncoder {
+    pub fn new(table_log: u32) -> TansEncoder {
+        TansEncoder {
+            table_log,
+            state: 1 << table_log,
+            chunks: Vec::new(),
+        }
+    }
+
+    /// Every table used with one encoder must have its table log.
+    pub fn put<T: Symbol>(&mut self, table: &TansTable<T>, symbol: T) {
+        let frequencies = &table.frequencies;
+        assert_eq!(frequencies.table_log, self.table_log);
+        let i = frequencies.indexes[&symbol];
+        let frequency = frequencies.frequencies[i];
+        let mut bits = 0;
+        while self.state >> bits >= 2 * frequency {
+            bits += 1;
+        }
+        self.chunks.push((self.state & ((1 << bits) - 1), bits));
+        let x = self.state >> bits;
+        self.state = table.encode[(frequencies.starts[i] + x - frequency) as usize];
+    }
+
+    /// The final state, then the bits in the order the decoder reads them.
+    pub fn finish(self) -> BitVec {
+        let mut output = BitVec::new();
+        push_bits(&mut output, (self.state - (1 << self.table_log)) as u64, self.table_log);
+        for &(value, bits) in self.chunks.iter().rev() {
+            push_bits(&mut output, value as u64, bits);
+        }
+        output
+    }
+}
+
+pub struct TansDecoder {
+    table_log: u32,
+    state: u32,
+}
+
+impl TansDecoder {
+    pub fn new(iter: &mut BitReader, table_log: u32) -> Result<TansDecoder, Error> {
+        Ok(TansDecoder {
+            table_log,
+            state: read_bits(iter, table_log)? as u32,
+        })
+    }
+
+    pub fn get<T: Symbol>(&mut self, table: &TansTable<T>, iter: &mut BitReader)
+                          -> Result<T, Error> {
+        assert_eq!(table.frequencies.table_log, self.table_log);
+        let transition = table.decode[self.state as usize];
+        self.state = transition.base + read_bits(iter, transition.bits)? as u32;
+        Ok(table.frequencies.symbols[transition.symbol as usize])
+    }
+}
+
+#[test]
+fn ans_testing() {
+    let mut input: Vec<u8> = vec![0; 5000];
+    input.extend(vec![1; 50]);
+    input.extend(0..=255);
+    let mut statistics = BTreeMap::new();
+    for &byte in &input {
+        *statistics.entry(byte).or_insert(0) += 1;
+    }
+    let log = table_log(statistics.len());
+    assert_eq!(log, 11);
+    let frequencies = Frequencies::from_statistics(&statistics, log);
+    assert_eq!(frequencies.frequencies.iter().sum::<u32>(), 1 << log);
+    assert!(frequencies.frequencies.iter().all(|&frequency| frequency >= 1));
+    let header = frequencies.encode();
+
+    let mut encoder = RansEncoder::new();
+    for &byte in input.iter().rev() {
+        encoder.put(&frequencies, byte);
+    }
+    let coded = encoder.finish();
+    // the entropy is about 3930 bits; the table rounds the rare symbols up
+    assert!(coded.len() < 4600);
+    let table = RansTable::new(Frequencies::decode(&mut BitReader::new(&header)).unwrap());
+    let mut iter = BitReader::new(&coded);
+    let mut decoder = RansDecoder::new(&mut iter).unwrap();
+    for &byte in &input {
+        assert_eq!(decoder.get(&table, &mut iter), Ok(byte));
+    }
+
+    let table = TansTable::new(Frequencies::decode(&mut BitReader::new(&header)).unwrap());
+    let mut encoder = TansEncoder::new(log);
+    for &byte in input.iter().rev() {
+        encoder.put(&table, byte);
+    }
+    let coded = encoder.finish();
+    assert!(coded.len() < 4600);
+    let mut iter = BitReader::new(&coded);
+    let mut decoder = TansDecoder::new(&mut iter, log).unwrap();
+    for &byte in &input {
+        assert_eq!(decoder.get(&table, &mut iter), Ok(byte));
+    }
+    assert_eq!(iter.remaining(), 0);
+
+    // the most skewed table still needs bits for its symbols
+    let statistics: BTreeMap<u8, u64> = vec![(0, 1 << 20), (1, 1)].into_iter().collect();
+    let frequencies = Frequencies::from_statistics(&statistics, MIN_TABLE_LOG);
+    assert_eq!(frequencies.frequencies, vec![31, 1]);
+    let mut encoder = RansEncoder::new();
+    for _ in 0..100_000 {
+        encoder.put(&frequencies, 0);
+    }
+    assert!(frequencies.max_symbols(encoder.finish().len()) >= 100_000);
+    let table = TansTable::new(frequencies);
+    let mut encoder = TansEncoder::new(MIN_TABLE_LOG);
+    for _ in 0..100_000 {
+        encoder.put(&table, 0);
+    }
+    assert!(table.frequencies.max_symbols(encoder.finish().len()) >= 100_000);
+
+    // a table with a missing slot
+    let mut header = BitVec::new();
+    push_bits(&mut header, 5, 5);
+    push_gamma(&mut header, 1);
+    push_gamma(&mut header, 1);
+    push_gamma(&mut header, 31);
+    assert!(Frequencies::<u8>::decode(&mut BitReader::new(&header)).is_err());
+
+    // frequencies whose sum wraps around to the table size
+    let mut header = BitVec::new();
+    push_bits(&mut header, 5, 5);
+    push_gamma(&mut header, 3);
+    for &frequency in &[16, u64::MAX, 17] {
+        push_gamma(&mut header, 1);
+        push_gamma(&mut header, frequency);
+    }
+    assert!(Frequencies::<u8>::decode(&mut BitReader::new(&header)).is_err());
+}
@@ -0,0 +1,446 @@
+//! Arithmetic coding with 32 bit integer bounds (Witten, Neal and Cleary), driven by order-0
+//! frequency models: a static one stored in a header, or an adaptive one that both sides update
+//! after every symbol.
+
+use bit_vec::BitVec;
+use bitvec_util::*;
+use error::{Error, Offset};
+use huffman::Symbol;
+use std::collections::BTreeMap;
+use std::marker::PhantomData;
+
+const PRECISION: u32 = 32;
+const TOP: u64 = (1 << PRECISION) - 1;
+const HALF: u64 = 1 << (PRECISION - 1);
+const QUARTER: u64 = 1 << (PRECISION - 2);
+
+/// Largest total frequency of a static model; it keeps every interval non-empty.
+pub const MAX_TOTAL: u32 = 1 << 24;
+/// The adaptive model halves its counts beyond this total.
+const ADAPTIVE_LIMIT: u32 = 1 << 16;
+const ADAPTIVE_INCREMENT: u32 = 32;
+
+pub trait Model<T> {
+    fn total(&self) -> u32;
+    /// Cumulative frequencies `[low, high)` of `symbol`.
+    fn interval(&self, symbol: T) -> (u32, u32);
+    /// The symbol whose interval holds `target`, with that interval, or `None` if `target`
+    /// is not below the total.
+    fn symbol(&self, target: u32) -> Option<(T, u32, u32)>;
+    fn update(&mut self, symbol: T);
+}
+
+/// Frequencies counted beforehand and sent in a header; only the symbols seen are coded.
+pub struct StaticModel<T> {
+    symbols: Vec<T>,
+    /// `cumulative[i]` is the sum of the frequencies of the symbols before `symbols[i]`.
+    cumulative: Vec<u32>,
+    indexes: BTreeMap<T, usize>,
+}
+
+impl<T: Symbol> StaticModel<T> {
+    /// Scales the statistics down to `MAX_TOTAL` if needed; every symbol keeps a frequency of
+    /// at least 1. A lone symbol gets a neighbor, since it would cost no bits at all.
+    pub fn from_statistics(statistics: &BTreeMap<T, u64>) -> StaticModel<T> {
+        assert!(!statistics.is_empty() && statistics.len() < MAX_TOTAL as usize / 2);
+        let mut statistics = statistics.clone();
+        if statistics.len() == 1 {
+            let symbol = *statistics.keys().next().unwrap();
+            statistics.insert(symbol.neighbor(), 0);
+        }
+        let sum: u64 = statistics.values().sum();
+        let budget = (MAX_TOTAL as usize - statistics.len()) as u64;
+        let frequencies = statistics.iter().map(|(&symbol, &count)| {
+            let frequency = if sum <= MAX_TOTAL as u64 { count } else { count * budget / sum };
+            (symbol, frequency.max(1) as u32)
+        });
+        StaticModel::from_frequencies(frequencies)
+    }
+
+    fn from_frequencies<I: Iterator<Item = (T, u32)>>(frequencies: I) -> StaticModel<T> {
+        let mut model = StaticModel {
+            symbols: Vec::new(),
+            cumulative: vec![0],
+            indexes: BTreeMap::new(),
+        };
+        for (symbol, frequency) in frequencies {
+            let total = model.total();
+            model.indexes.insert(symbol, model.symbols.len());
+            model.symbols.push(symbol);
+            model.cumulative.push(total + frequency);
+        }
+        model
+    }
+
+    /// The symbol count, then each symbol (as the gap from the previous one) and frequency.
+    pub fn encode(&self) -> BitVec {
+        let mut output = BitVec::new();
+        push_gamma(&mut output, self.symbols.len() as u64);
+        let mut next = 0;
+        for (i, symbol) in self.symbols.iter().enumerate() {
+            push_gamma(&mut output, symbol.index() - next + 1);
+            push_gamma(&mut output, (self.cumulative[i + 1] - self.cumulative[i]) as u64);
+            next = symbol.index() + 1;
+        }
+        output
+    }
+
+    pub fn decode(iter: &mut BitReader) -> Result<StaticModel<T>, Error> {
+        let start = iter.offset();
+        let count = read_gamma(iter)?;
+        if count < 2 || count > MAX_TOTAL as u64 {
+            return Err(Error::InvalidHeader { offset: Offset::Bit(start) });
+        }
+        let mut frequencies = Vec::new();
+        let (mut next, mut total) = (0u64, 0u64);
+        for _ in 0..count {
+            let index = next.checked_add(read_gamma(iter)? - 1);
+            let frequency = read_gamma(iter)?;
+            // compared before adding, so that a huge frequency cannot overflow the total
+            let symbol = match index.and_then(T::from_index) {
+                Some(symbol) if frequency <= MAX_TOTAL as u64 - total => symbol,
+                _ => return Err(Error::InvalidHeader { offset: Offset::Bit(start) }),
+            };
+            total += frequency;
+            frequencies.push((symbol, frequency as u32));
+            next = symbol.index() + 1;
+        }
+        Ok(StaticModel::from_frequencies(frequencies.into_iter()))
+    }
+
+    /// Most symbols that `bits` bits of code can hold. Each symbol leaves at most `1 - gap / 2`
+    /// of the interval, where `gap` is the share of the total outside the largest frequency,
+    /// and every bit read doubles it again.
+    pub fn max_symbols(&self, bits: usize) -> u64 {
+        let total = self.total() as u64;
+        let largest = self.cumulative.windows(2).map(|pair| pair[1] - pair[0]).max().unwrap();
+        (bits as u64 + PRECISION as u64).saturating_mul(2 * total) / (total - largest as u64)
+    }
+}
+
+impl<T: Symbol> Model<T> for StaticModel<T> {
+    fn total(&self) -> u32 {
+        self.cumulative[self.symbols.len()]
+    }
+
+    fn interval(&self, symbol: T) -> (u32, u32) {
+        let i = self.indexes[&symbol];
+        (self.cumulative[i], self.cumulative[i + 1])
+    }
+
+    fn symbol(&self, target: u32) -> Option<(T, u32, u32)> {
+        if target >= self.total() {
+            return None;
+        }
+        // the last cumulative frequency not above the target
+        let i = self.cumulative.partition_point(|&low| low <= target) - 1;
+        Some((self.symbols[i], self.cumulative[i], self.cumulative[i + 1]))
+    }
+
+    fn update(&mut self, _: T) {}
+}
+
+/// Counts of the symbols `0..alphabet` seen so far, all starting at 1, in a Fenwick tree.
+pub struct AdaptiveModel<T> {
+    frequencies: Vec<u32>,
+    tree: Vec<u32>,
+    total: u32,
+    phantom: PhantomData<T>,
+}
+
+impl<T: Symbol> AdaptiveModel<T> {
+    pub fn new(alphabet: usize) -> AdaptiveModel<T> {
+        assert!(alphabet >= 1 && alphabet <= (ADAPTIVE_LIMIT / 2) as usize);
+        let mut model = AdaptiveModel {
+            frequencies: vec![1; alphabet],
+            tree: Vec::new(),
+            total: 0,
+            phantom: PhantomData,
+        };
+        model.rebuild();
+        model
+    }
+
+    fn rebuild(&mut self) {
+        self.tree = vec![0; self.frequencies.len() + 1];
+        self.total = 0;
+        for i in 0..self.frequencies.len() {
+            let frequency = self.frequencies[i];
+            self.add(i, frequency);
+        }
+    }
+
+    fn add(&mut self, i: usize, value: u32) {
+        self.total += value;
+        let mut node = i + 1;
+        while node < self.tree.len() {
+            self.tree[node] += value;
+            node += node & node.wrapping_neg();
+        }
+    }
+
+    /// Sum of the frequencies of the symbols before `i`.
+    fn prefix(&self, i: usize) -> u32 {
+        let (mut node, mut sum) = (i, 0);
+        while node > 0 {
+            sum += self.tree[node];
+            node &= node - 1;
+        }
+        sum
+    }
+}
+
+impl<T: Symbol> Model<T> for AdaptiveModel<T> {
+    fn total(&self) -> u32 {
+        self.total
+    }
+
+    fn interval(&self, symbol: T) -> (u32, u32) {
+        let i = symbol.index() as usize;
+        let low = self.prefix(i);
+        (low, low + self.frequencies[i])
+    }
+
+    fn symbol(&self, target: u32) -> Option<(T, u32, u32)> {
+        if target >= self.total {
+            return None;
+        }
+        // descend the tree to the last prefix sum not above the target
+        let (mut i, mut low) = (0, 0);
+        let mut step = self.tree.len().next_power_of_two() / 2;
+        while step > 0 {
+            if i + step < self.tree.len() && low + self.tree[i + step] <= target {
+                i += step;
+                low += self.tree[i];
+            }
+            step /= 2;
+        }
+        Some((T::from_index(i as u64).unwrap(), low, low + self.frequencies[i]))
+    }
+
+    fn update(&mut self, symbol: T) {
+        self.add(symbol.index() as usize, ADAPTIVE_INCREMENT);
+        self.frequencies[symbol.index() as usize] += ADAPTIVE_INCREMENT;
+        if self.total > ADAPTIVE_LIMIT {
+            for frequency in &mut self.frequencies {
+                *frequency = frequency.div_ceil(2);
+            }
+            self.rebuild();
+        }
+    }
+}
+
+pub struct ArithmeticEncoder {
+    low: u64,
+    high: u64,
+    pending: u64,
+    output: BitVec,
+}
+
+impl ArithmeticEncoder {
+    pub fn new() -> ArithmeticEncoder {
+        ArithmeticEncoder {
+            low: 0,
+            high: TOP,
+            pending: 0,
+            output: BitVec::new(),
+        }
+    }
+
+    pub fn encode<T: Copy, M: Model<T>>(&mut self, model: &mut M, symbol: T) {
+        let (low, high) = model.interval(symbol);
+        let range = self.high - self.low + 1;
+        let total = model.total() as u64;
+        self.high = self.low + range * high as u64 / total - 1;
+        self.low += range * low as u64 / total;
+        loop {
+            if self.high < HALF {
+                self.emit(false);
+            } else if self.low >= HALF {
+                self.emit(true);
+                self.low -= HALF;
+                self.high -= HALF;
+            } else if self.low >= QUARTER && self.high < 3 * QUARTER {
+                self.pending += 1;
+                self.low -= QUARTER;
+                self.high -= QUARTER;
+            } else {
+                break;
+            }
+            self.low *= 2;
+            self.high = 2 * self.high + 1;
+        }
+        model.update(symbol);
+    }
+
+    fn emit(&mut self, bit: bool) {
+        self.output.push(bit);
+        for _ in 0..self.pending {
+            self.output.push(!bit);
+        }
+        self.pending = 0;
+    }
+
+    /// Writes enough bits to single out the final interval; the decoder reads zeros after them.
+    pub fn finish(mut self) -> BitVec {
+        self.pending += 1;
+        let bit = self.low >= QUARTER;
+        self.emit(bit);
+        self.output
+    }
+}
+
+pub struct ArithmeticDecoder {
+    low: u64,
+    high: u64,
+    value: u64,
+    /// Zero bits read past the end of the input.
+    overrun: u32,
+}
+
+impl ArithmeticDecoder {
+    pub fn new(iter: &mut BitReader) -> ArithmeticDecoder {
+        let mut decoder = ArithmeticDecoder {
+            low: 0,
+            high: TOP,
+            value: 0,
+            overrun: 0,
+        };
+        for _ in 0..PRECISION {
+            decoder.value = 2 * decoder.value + decoder.next_bit(iter);
+        }
+        decoder
+    }
+
+    fn next_bit(&mut self, iter: &mut BitReader) -> u64 {
+        match iter.next() {
+            Some(bit) => bit as u64,
+            None => {
+                self.overrun += 1;
+                0
+            }
+        }
+    }
+
+    pub fn decode<T: Copy, M: Model<T>>(&mut self,
+                                        model: &mut M,
+                                        iter: &mut BitReader)
+                                        -> Result<T, Error> {
+        // the encoder leaves out the last `PRECISION - 2` bits read ahead, and no more
+        if self.overrun > PRECISION - 2 {
+            return Err(Error::UnexpectedEof { offset: Offset::Bit(iter.offset()) });
+        }
+        // a valid stream keeps the value in the interval, and never codes with an empty model
+        let invalid = Error::InvalidHeader { offset: Offset::Bit(iter.offset()) };
+        let total = model.total() as u64;
+        if total == 0 || self.value < self.low || self.value > self.high {
+            return Err(invalid);
+        }
+        let range = self.high - self.low + 1;
+        let target = ((self.value - self.low + 1) * total - 1) / range;
+        let (symbol, low, high) = model.symbol(target as u32).ok_or(invalid)?;
+        self.high = self.low + range * high as u64 / total - 1;
+        self.low += range * low as u64 / total;
+        loop {
+            if self.high < HALF {
+            } else if self.low >= HALF {
+                self.value -= HALF;
+                self.low -= HALF;
+                self.high -= HALF;
+            } else if self.low >= QUARTER && self.high < 3 * QUARTER {
+                self.value -= QUARTER;
+                self.low -= QUARTER;
+                self.high -= QUARTER;
+            } else {
+                break;
+            }
+            self.low *= 2;
+            self.high = 2 * self.high + 1;
+            self.value = 2 * self.value + self.next_bit(iter);
+        }
+        model.update(symbol);
+        Ok(symbol)
+    }
+}
+
+#[test]
+fn arithmetic_testing() {
+    let mut input: Vec<u8> = vec![0; 5000];
+    input.extend(vec![1; 50]);
+    input.extend(0..=255);
+
+    let mut statistics = BTreeMap::new();
+    for &byte in &input {
+        *statistics.entry(byte).or_insert(0) += 1;
+    }
+    let model = StaticModel::from_statistics(&statistics);
+    let mut encoder = ArithmeticEncoder::new();
+    let mut static_model = StaticModel::decode(&mut BitReader::new(&model.encode())).unwrap();
+    for &byte in &input {
+        encoder.encode(&mut static_model, byte);
+    }
+    let coded = encoder.finish();
+    // the entropy is about 3930 bits, where Huffman needs at least one bit per symbol
+    assert!(coded.len() < 4000);
+    let mut iter = BitReader::new(&coded);
+    let mut decoder = ArithmeticDecoder::new(&mut iter);
+    let mut static_model = StaticModel::decode(&mut BitReader::new(&model.encode())).unwrap();
+    for &byte in &input {
+        assert_eq!(decoder.decode(&mut static_model, &mut iter), Ok(byte));
+    }
+    // the decoder reads ahead of the last bit exactly as far as the encoder left out
+    assert_eq!(decoder.overrun, PRECISION - 2);
+
+    let mut encoder = ArithmeticEncoder::new();
+    let mut model: AdaptiveModel<u64> = AdaptiveModel::new(257);
+    for &byte in &input {
+        encoder.encode(&mut model, byte as u64);
+    }
+    let coded = encoder.finish();
+    assert!(coded.len() < 4500);
+    let mut iter = BitReader::new(&coded);
+    let mut decoder = ArithmeticDecoder::new(&mut iter);
+    let mut model: AdaptiveModel<u64> = AdaptiveModel::new(257);
+    for &byte in &input {
+        assert_eq!(decoder.decode(&mut model, &mut iter), Ok(byte as u64));
+    }
+
+    // a truncated stream runs out of bits instead of decoding zeros forever
+    let mut truncated = coded.clone();
+    truncated.truncate(coded.len() / 2);
+    let mut iter = BitReader::new(&truncated);
+    let mut decoder = ArithmeticDecoder::new(&mut iter);
+    let mut model: AdaptiveModel<u64> = AdaptiveModel::new(257);
+    let result: Result<Vec<u64>, Error> =
+        input.iter().map(|_| decoder.decode(&mut model, &mut iter)).collect();
+    assert_eq!(result, Err(Error::UnexpectedEof { offset: Offset::Bit(truncated.len()) }));
+
+    // large counts are scaled down, and symbols far apart are fine
+    let statistics: BTreeMap<u64, u64> = vec![(3, 1 << 40), (1 << 50, 1)].into_iter().collect();
+    let model = StaticModel::from_statistics(&statistics);
+    assert!(model.total() <= MAX_TOTAL);
+    let decoded: StaticModel<u64> = StaticModel::decode(&mut BitReader::new(&model.encode()))
+        .unwrap();
+    assert_eq!(decoded.interval(1 << 50), (model.total() - 1, model.total()));
+
+    // a lone symbol would cost nothing, so it is coded next to another one
+    let statistics: BTreeMap<u8, u64> = vec![(255, 1000)].into_iter().collect();
+    let model = StaticModel::from_statistics(&statistics);
+    assert_eq!((model.interval(254), model.interval(255)), ((0, 1), (1, 1001)));
+    assert_eq!(model.max_symbols(8), 2 * (8 + PRECISION as u64) * 1001);
+    let mut header = BitVec::new();
+    push_gamma(&mut header, 1);
+    push_gamma(&mut header, 8);
+    push_gamma(&mut header, 1000);
+    assert!(StaticModel::<u8>::decode(&mut BitReader::new(&header)).is_err());
+
+    // two frequencies whose sum wraps around are rejected
+    let mut header = BitVec::new();
+    push_gamma(&mut header, 2);
+    for &frequency in &[1, u64::MAX] {
+        push_gamma(&mut header, 1);
+        push_gamma(&mut header, frequency);
+    }
+    assert_eq!(StaticModel::<u8>::decode(&mut BitReader::new(&header)).err(),
+               Some(Error::InvalidHeader { offset: Offset::Bit(0) }));
+}
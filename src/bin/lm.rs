@@ -0,0 +1,227 @@
+extern crate data_compression;
+use data_compression::{Chain, Checksum, Header, Registry};
+use std::env;
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::io::prelude::*;
+use std::process;
+
+const USAGE: &str = "Usage:
+    lm compress [--algo ALGO] [--checksum CHECKSUM] [-o OUTPUT] INPUT
+    lm decompress [-o OUTPUT] INPUT
+    lm test INPUT
+    lm info INPUT
+
+Options:
+    -a, --algo ALGO          codecs joined by '+', e.g. huffman, lz78, lz77+huffman
+                             (default: lz77+huffman, 'store' for no compression)
+                             'lm --list' shows the available codecs
+    -c, --checksum CHECKSUM  crc32 (default), adler32 or none
+    -o, --output OUTPUT      output file (default: INPUT.lm, or INPUT without .lm)
+    -h, --help               print this message
+    -l, --list               list the available codecs";
+
+const COMMANDS: [&str; 4] = ["compress", "decompress", "test", "info"];
+const DEFAULT_ALGO: &str = "lz77+huffman";
+const EXTENSION: &str = ".lm";
+
+enum CliError {
+    Usage(String),
+    Io(String, io::Error),
+    Data(String, data_compression::Error),
+}
+
+impl CliError {
+    fn exit_code(&self) -> i32 {
+        match *self {
+            CliError::Io(..) => 1,
+            CliError::Usage(_) => 2,
+            CliError::Data(..) => 3,
+        }
+    }
+}
+
+impl fmt::Display for CliError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CliError::Usage(ref message) => write!(f, "{}", message),
+            CliError::Io(ref path, ref err) => write!(f, "{}: {}", path, err),
+            CliError::Data(ref path, ref err) => write!(f, "{}: {}", path, err),
+        }
+    }
+}
+
+struct Options {
+    input: String,
+    output: Option<String>,
+    chain: Chain,
+    checksum: Checksum,
+}
+
+fn parse_chain(registry: &Registry, names: &str) -> Result<Chain, CliError> {
+    registry.chain(names)
+        .map_err(|name| CliError::Usage(format!("unknown algorithm '{}'", name)))
+}
+
+/// Options of `command`; those it does not use are rejected rather than ignored.
+fn parse_options(registry: &Registry, command: &str, args: &[String])
+                 -> Result<Options, CliError> {
+    let mut input = None;
+    let mut output = None;
+    let mut chain = parse_chain(registry, DEFAULT_ALGO)?;
+    let mut checksum = Checksum::default();
+
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        let mut value = || {
+            iter.next()
+                .cloned()
+                .ok_or_else(|| CliError::Usage(format!("missing value for {}", arg)))
+        };
+        let accepted = match arg.as_str() {
+            "-o" | "--output" => ["compress", "decompress"].contains(&command),
+            "-a" | "--algo" | "-c" | "--checksum" => command == "compress",
+            _ => true,
+        };
+        if !accepted {
+            return Err(CliError::Usage(format!("'{}' does not accept option '{}'", command, arg)));
+        }
+        match arg.as_str() {
+            "-o" | "--output" => output = Some(value()?),
+            "-a" | "--algo" => chain = parse_chain(registry, &value()?)?,
+            "-c" | "--checksum" => {
+                let name = value()?;
+                checksum = Checksum::from_name(&name)
+                    .ok_or_else(|| CliError::Usage(format!("unknown checksum '{}'", name)))?;
+            }
+            _ if arg.starts_with('-') && arg.len() > 1 => {
+                return Err(CliError::Usage(format!("unknown option '{}'", arg)));
+            }
+            _ if input.is_none() => input = Some(arg.clone()),
+            _ => return Err(CliError::Usage(format!("unexpected argument '{}'", arg))),
+        }
+    }
+
+    Ok(Options {
+        input: input.ok_or_else(|| CliError::Usage("missing INPUT".to_string()))?,
+        output,
+        chain,
+        checksum,
+    })
+}
+
+fn read_file(path: &str) -> Result<Vec<u8>, CliError> {
+    let mut contents = Vec::new();
+    File::open(path)
+        .and_then(|mut file| file.read_to_end(&mut contents))
+        .map_err(|err| CliError::Io(path.to_string(), err))?;
+    Ok(contents)
+}
+
+fn write_file(path: &str, contents: &[u8]) -> Result<(), CliError> {
+    File::create(path)
+        .and_then(|mut file| file.write_all(contents))
+        .map_err(|err| CliError::Io(path.to_string(), err))
+}
+
+fn compress(options: Options) -> Result<(), CliError> {
+    let contents = read_file(&options.input)?;
+    let output = data_compression::compress_with_checksum(&contents,
+                                                          &options.chain,
+                                                          options.checksum);
+    let input = options.input;
+    let path = options.output.unwrap_or_else(|| input.clone() + EXTENSION);
+    write_file(&path, &output)
+}
+
+fn decompress(registry: &Registry, options: Options) -> Result<(), CliError> {
+    let input = options.input;
+    let path = match options.output {
+        Some(path) => path,
+        None if input.len() > EXTENSION.len() && input.ends_with(EXTENSION) => {
+            input[..input.len() - EXTENSION.len()].to_string()
+        }
+        None => {
+            return Err(CliError::Usage(format!("cannot derive an output name from '{}', use -o",
+                                               input)))
+        }
+    };
+    let contents = read_file(&input)?;
+    let output = data_compression::decompress_with(registry, &contents)
+        .map_err(|err| CliError::Data(input.clone(), err))?;
+    write_file(&path, &output)
+}
+
+fn test(registry: &Registry, options: Options) -> Result<(), CliError> {
+    let contents = read_file(&options.input)?;
+    data_compression::decompress_with(registry, &contents)
+        .map_err(|err| CliError::Data(options.input.clone(), err))?;
+    println!("{}: ok", options.input);
+    Ok(())
+}
+
+fn info(registry: &Registry, options: Options) -> Result<(), CliError> {
+    let contents = read_file(&options.input)?;
+    let (header, _) = Header::read(&contents)
+        .map_err(|err| CliError::Data(options.input.clone(), err))?;
+
+    let pipeline: Vec<String> = header.pipeline
+        .iter()
+        .map(|&id| registry.name(id).map_or_else(|| format!("#{}", id), |name| name.to_string()))
+        .collect();
+    let pipeline = if pipeline.is_empty() { "store".to_string() } else { pipeline.join("+") };
+
+    println!("file:            {}", options.input);
+    println!("format version:  {}", header.version);
+    println!("pipeline:        {}", pipeline);
+    println!("checksum:        {}", header.checksum.name());
+    println!("original size:   {} bytes", header.original_len);
+    println!("compressed size: {} bytes", contents.len());
+    if header.original_len > 0 {
+        println!("ratio:           {:.1}%",
+                 100.0 * contents.len() as f64 / header.original_len as f64);
+    }
+    Ok(())
+}
+
+fn run(args: &[String]) -> Result<(), CliError> {
+    let command = match args.first() {
+        Some(command) => command.as_str(),
+        None => return Err(CliError::Usage("missing command".to_string())),
+    };
+    if args.iter().any(|arg| arg == "-h" || arg == "--help") {
+        println!("{}", USAGE);
+        return Ok(());
+    }
+
+    let registry = Registry::default();
+    if command == "-l" || command == "--list" {
+        for name in registry.names() {
+            println!("{}", name);
+        }
+        return Ok(());
+    }
+
+    if !COMMANDS.contains(&command) {
+        return Err(CliError::Usage(format!("unknown command '{}'", command)));
+    }
+    let options = parse_options(&registry, command, &args[1..])?;
+    match command {
+        "compress" => compress(options),
+        "decompress" => decompress(&registry, options),
+        "test" => test(&registry, options),
+        _ => info(&registry, options),
+    }
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if let Err(err) = run(&args) {
+        eprintln!("lm: {}", err);
+        if let CliError::Usage(_) = err {
+            eprintln!("\n{}", USAGE);
+        }
+        process::exit(err.exit_code());
+    }
+}
@@ -1,4 +1,6 @@
 use bit_vec::BitVec;
+use error::{Error, Offset};
+use std::cmp;
 
 pub fn append_bit_vec(mut a: BitVec, b: &BitVec) -> BitVec {
     a.extend(b.iter());
@@ -12,11 +14,17 @@ pub fn serialize_bit_vec(x: &BitVec) -> Vec<u8> {
     output
 }
 
-pub fn deserialize_bit_vec(x: &[u8]) -> BitVec {
-    let mut bits = BitVec::from_bytes(&x[..x.len() - 1]);
-    let len = bits.len() - x[x.len() - 1] as usize;
-    bits.truncate(len);
-    bits
+pub fn deserialize_bit_vec(x: &[u8]) -> Result<BitVec, Error> {
+    // the last byte counts the padding bits of the one before it
+    match x.last() {
+        Some(&padding) if padding < 8 && (padding == 0 || x.len() > 1) => {
+            let mut bits = BitVec::from_bytes(&x[..x.len() - 1]);
+            let len = bits.len() - padding as usize;
+            bits.truncate(len);
+            Ok(bits)
+        }
+        _ => Err(Error::UnexpectedEof { offset: Offset::Byte(x.len().saturating_sub(1)) }),
+    }
 }
 
 #[allow(dead_code)]
@@ -28,3 +36,91 @@ pub fn pop_front_bit_vec(mut to_pop: BitVec) -> BitVec {
     to_pop.pop();
     to_pop
 }
+
+/// Bit iterator that remembers how many bits it has consumed, for error reporting.
+pub struct BitReader<'a> {
+    bits: &'a BitVec,
+    position: usize,
+}
+
+impl<'a> BitReader<'a> {
+    pub fn new(bits: &'a BitVec) -> BitReader<'a> {
+        BitReader { bits, position: 0 }
+    }
+
+    pub fn offset(&self) -> usize {
+        self.position
+    }
+
+    pub fn remaining(&self) -> usize {
+        self.bits.len() - self.position
+    }
+
+    /// The next `count` bits (at most 32) without consuming them, the first one most
+    /// significant, and how many of them exist; missing bits read as zeros.
+    pub fn peek(&self, count: u32) -> (u32, u32) {
+        if count == 0 {
+            return (0, 0);
+        }
+        let available = cmp::min(count as usize, self.remaining()) as u32;
+        // bit i of a BitVec is bit i % 32 of its storage word i / 32
+        let storage = self.bits.storage();
+        let word = self.position / 32;
+        let low = storage.get(word).map_or(0, |&bits| bits as u64);
+        let high = storage.get(word + 1).map_or(0, |&bits| bits as u64);
+        let window = (high << 32 | low) >> (self.position % 32) & ((1 << available) - 1);
+        ((window.reverse_bits() >> (64 - count)) as u32, available)
+    }
+
+    pub fn skip(&mut self, count: u32) {
+        self.position = cmp::min(self.position + count as usize, self.bits.len());
+    }
+}
+
+impl<'a> Iterator for BitReader<'a> {
+    type Item = bool;
+    fn next(&mut self) -> Option<bool> {
+        let bit = self.bits.get(self.position)?;
+        self.position += 1;
+        Some(bit)
+    }
+}
+
+/// Appends the `count` low bits of `value`, most significant first.
+pub fn push_bits(output: &mut BitVec, value: u64, count: u32) {
+    for i in (0..count).rev() {
+        output.push(value >> i & 1 == 1);
+    }
+}
+
+pub fn read_bits(iter: &mut BitReader, count: u32) -> Result<u64, Error> {
+    let mut value = 0;
+    for _ in 0..count {
+        match iter.next() {
+            Some(bit) => value = value << 1 | bit as u64,
+            None => return Err(Error::UnexpectedEof { offset: Offset::Bit(iter.offset()) }),
+        }
+    }
+    Ok(value)
+}
+
+/// Elias gamma code of `value`, which must not be 0: small values take few bits.
+pub fn push_gamma(output: &mut BitVec, value: u64) {
+    let width = 64 - value.leading_zeros();
+    push_bits(output, 0, width - 1);
+    push_bits(output, value, width);
+}
+
+pub fn read_gamma(iter: &mut BitReader) -> Result<u64, Error> {
+    let start = iter.offset();
+    let mut width = 1;
+    loop {
+        match iter.next() {
+            Some(true) => break,
+            Some(false) if width < 64 => width += 1,
+            Some(false) => return Err(Error::InvalidHeader { offset: Offset::Bit(start) }),
+            None => return Err(Error::UnexpectedEof { offset: Offset::Bit(iter.offset()) }),
+        }
+    }
+    Ok(1 << (width - 1) | read_bits(iter, width - 1)?)
+}
@@ -0,0 +1,270 @@
+//! Burrows–Wheeler transform of blocks, through suffix arrays built by SA-IS, followed as in
+//! bzip2 by move-to-front and zero-run coding (RUNA/RUNB) before Huffman coding.
+
+use mtf::MoveToFront;
+use rle::{zero_run_coding, zero_run_decoding};
+
+pub const BLOCK_SIZE: usize = 900_000;
+pub const MAX_BLOCK_SIZE: usize = 1 << 26;
+
+/// MTF index `i > 0` becomes `i + 1`; this ends a block.
+pub const EOB: u16 = 257;
+
+const EMPTY: usize = usize::MAX;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BwtParams {
+    pub block_size: usize,
+}
+
+impl Default for BwtParams {
+    fn default() -> BwtParams {
+        BwtParams { block_size: BLOCK_SIZE }
+    }
+}
+
+impl BwtParams {
+    pub fn new(block_size: usize) -> BwtParams {
+        let params = BwtParams { block_size };
+        assert!(params.is_valid(), "invalid BWT parameters {:?}", params);
+        params
+    }
+
+    pub fn is_valid(&self) -> bool {
+        (1..=MAX_BLOCK_SIZE).contains(&self.block_size)
+    }
+}
+
+fn bucket_starts(counts: &[usize]) -> Vec<usize> {
+    let mut sum = 0;
+    counts.iter()
+        .map(|&count| {
+            sum += count;
+            sum - count
+        })
+        .collect()
+}
+
+fn bucket_ends(counts: &[usize]) -> Vec<usize> {
+    let mut sum = 0;
+    counts.iter()
+        .map(|&count| {
+            sum += count;
+            sum
+        })
+        .collect()
+}
+
+/// Sorts the L-type suffixes from the sorted LMS ones, then the S-type ones.
+fn induce(text: &[usize], sa: &mut [usize], stype: &[bool], counts: &[usize]) {
+    let mut heads = bucket_starts(counts);
+    for i in 0..sa.len() {
+        if sa[i] != EMPTY && sa[i] > 0 && !stype[sa[i] - 1] {
+            let j = sa[i] - 1;
+            sa[heads[text[j]]] = j;
+            heads[text[j]] += 1;
+        }
+    }
+    let mut tails = bucket_ends(counts);
+    for i in (0..sa.len()).rev() {
+        if sa[i] != EMPTY && sa[i] > 0 && stype[sa[i] - 1] {
+            let j = sa[i] - 1;
+            tails[text[j]] -= 1;
+            sa[tails[text[j]]] = j;
+        }
+    }
+}
+
+/// Suffix array of `text` by induced sorting (Nong, Zhang and Chan). The last symbol must be
+/// 0 and occur nowhere else; the others are below `alphabet`.
+pub fn suffix_array(text: &[usize], alphabet: usize) -> Vec<usize> {
+    let n = text.len();
+    if n == 1 {
+        return vec![0];
+    }
+    // suffix i is S-type if it is smaller than suffix i + 1
+    let mut stype = vec![true; n];
+    for i in (0..n - 1).rev() {
+        stype[i] = text[i] < text[i + 1] || (text[i] == text[i + 1] && stype[i + 1]);
+    }
+    let is_lms = |i: usize| i > 0 && stype[i] && !stype[i - 1];
+    let mut counts = vec![0; alphabet];
+    for &symbol in text {
+        counts[symbol] += 1;
+    }
+
+    // sort the LMS substrings
+    let mut sa = vec![EMPTY; n];
+    let mut tails = bucket_ends(&counts);
+    for i in (1..n).filter(|&i| is_lms(i)) {
+        tails[text[i]] -= 1;
+        sa[tails[text[i]]] = i;
+    }
+    induce(text, &mut sa, &stype, &counts);
+
+    // name them in order, equal substrings getting equal names
+    let same = |a: usize, b: usize| {
+        if a == n - 1 || b == n - 1 {
+            return a == b;
+        }
+        let mut i = 0;
+        loop {
+            let (x, y) = (a + i, b + i);
+            if text[x] != text[y] || stype[x] != stype[y] {
+                return false;
+            }
+            if i > 0 && (is_lms(x) || is_lms(y)) {
+                return is_lms(x) && is_lms(y);
+            }
+            i += 1;
+        }
+    };
+    let mut names = vec![EMPTY; n];
+    let mut name = 0;
+    let mut previous = EMPTY;
+    for &position in sa.iter().filter(|&&position| is_lms(position)) {
+        if previous == EMPTY || !same(previous, position) {
+            name += 1;
+        }
+        names[position] = name - 1;
+        previous = position;
+    }
+
+    // sort the LMS suffixes through the string of names, recursively if names repeat
+    let lms: Vec<usize> = (1..n).filter(|&i| is_lms(i)).collect();
+    let reduced: Vec<usize> = lms.iter().map(|&i| names[i]).collect();
+    let reduced_sa = if name < reduced.len() {
+        suffix_array(&reduced, name)
+    } else {
+        let mut reduced_sa = vec![0; reduced.len()];
+        for (i, &name) in reduced.iter().enumerate() {
+            reduced_sa[name] = i;
+        }
+        reduced_sa
+    };
+
+    sa.iter_mut().for_each(|position| *position = EMPTY);
+    let mut tails = bucket_ends(&counts);
+    for &i in reduced_sa.iter().rev() {
+        let position = lms[i];
+        tails[text[position]] -= 1;
+        sa[tails[text[position]]] = position;
+    }
+    induce(text, &mut sa, &stype, &counts);
+    sa
+}
+
+/// Last column of the sorted rotations of `block` followed by an end marker smaller than any
+/// byte, without the marker, and the row where the marker was (the primary index).
+pub fn bwt(block: &[u8]) -> (Vec<u8>, usize) {
+    let mut text: Vec<usize> = block.iter().map(|&byte| byte as usize + 1).collect();
+    text.push(0);
+    let mut last = Vec::with_capacity(block.len());
+    let mut primary = 0;
+    for (row, &position) in suffix_array(&text, 257).iter().enumerate() {
+        if position == 0 {
+            primary = row;
+        } else {
+            last.push(block[position - 1]);
+        }
+    }
+    (last, primary)
+}
+
+/// Undoes `bwt`, or returns `None` if the primary index does not fit the column.
+pub fn inverse_bwt(last: &[u8], primary: usize) -> Option<Vec<u8>> {
+    let n = last.len();
+    if primary == 0 || primary > n {
+        return if n == 0 && primary == 0 { Some(Vec::new()) } else { None };
+    }
+    let column = |row: usize| if row == primary {
+        0
+    } else {
+        last[row - (row > primary) as usize] as usize + 1
+    };
+    let mut counts = vec![0; 257];
+    for row in 0..n + 1 {
+        counts[column(row)] += 1;
+    }
+    // row of the rotation starting with the last byte of each row's rotation
+    let mut next = bucket_starts(&counts);
+    let mut previous = vec![0; n + 1];
+    for (row, previous) in previous.iter_mut().enumerate() {
+        let symbol = column(row);
+        *previous = next[symbol];
+        next[symbol] += 1;
+    }
+
+    let mut block = vec![0; n];
+    let mut row = 0;
+    for k in (0..n).rev() {
+        match column(row) {
+            0 => return None,
+            symbol => block[k] = (symbol - 1) as u8,
+        }
+        row = previous[row];
+    }
+    Some(block)
+}
+
+/// Move-to-front indexes of the BWT output, with zero runs as RUNA/RUNB digits, then EOB.
+pub fn mtf_runs(last: &[u8]) -> Vec<u16> {
+    let mut mtf = MoveToFront::bytes();
+    let indexes: Vec<u16> = last.iter().map(|&byte| mtf.encode(byte).unwrap() as u16).collect();
+    let mut symbols = zero_run_coding(&indexes);
+    symbols.push(EOB);
+    symbols
+}
+
+/// Undoes `mtf_runs` (the symbols without EOB), or returns `None` if they do not make a valid
+/// block of at most `max_len` bytes.
+pub fn inverse_mtf_runs(symbols: &[u16], max_len: usize) -> Option<Vec<u8>> {
+    if symbols.iter().any(|&symbol| symbol >= EOB) {
+        return None;
+    }
+    let mut mtf = MoveToFront::bytes();
+    zero_run_decoding(symbols, max_len)?
+        .into_iter()
+        .map(|index| mtf.decode(index as usize))
+        .collect()
+}
+
+#[test]
+fn bwt_testing() {
+    let (last, primary) = bwt(b"banana");
+    // rotations of "banana$" sorted: $banana, a$banan, ana$ban, anana$b, banana$, na$bana,
+    // nana$ba
+    assert_eq!((&last[..], primary), (&b"annbaa"[..], 4));
+    assert_eq!(inverse_bwt(&last, primary), Some(b"banana".to_vec()));
+    assert_eq!(inverse_bwt(&last, 7), None);
+
+    // the suffix array against plain sorting, on inputs that recurse
+    let mut x: u32 = 1;
+    for &(len, modulo) in &[(1, 2), (2, 2), (100, 2), (1000, 3), (5000, 256)] {
+        let mut block = Vec::new();
+        for _ in 0..len {
+            x = x.wrapping_mul(22695477).wrapping_add(1);
+            block.push(((x >> 16) % modulo) as u8);
+        }
+        let mut text: Vec<usize> = block.iter().map(|&byte| byte as usize + 1).collect();
+        text.push(0);
+        let mut expected: Vec<usize> = (0..text.len()).collect();
+        expected.sort_by(|&a, &b| text[a..].cmp(&text[b..]));
+        assert_eq!(suffix_array(&text, 257), expected);
+
+        let (last, primary) = bwt(&block);
+        assert_eq!(inverse_bwt(&last, primary), Some(block));
+
+        let symbols = mtf_runs(&last);
+        assert_eq!(symbols.last(), Some(&EOB));
+        let len = symbols.len() - 1;
+        assert_eq!(inverse_mtf_runs(&symbols[..len], last.len()), Some(last.clone()));
+        if !last.is_empty() {
+            assert_eq!(inverse_mtf_runs(&symbols[..len], last.len() - 1), None);
+        }
+    }
+
+    // runs of 1 to 6 zeros: A, B, AA, BA, AB, BB
+    let symbols = mtf_runs(&[0; 6]);
+    assert_eq!(symbols, vec![::rle::RUNB, ::rle::RUNB, EOB]);
+}
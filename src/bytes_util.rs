@@ -0,0 +1,49 @@
+use error::{Error, Offset};
+
+/// Appends `x` as a LEB128 varint: 7 bits per byte, least significant first.
+pub fn write_varint(output: &mut Vec<u8>, mut x: u64) {
+    while x >= 0x80 {
+        output.push((x & 0x7f) as u8 | 0x80);
+        x >>= 7;
+    }
+    output.push(x as u8);
+}
+
+/// Reads a varint written by `write_varint` at `*position` and advances `*position`.
+pub fn read_varint(content: &[u8], position: &mut usize) -> Result<u64, Error> {
+    let start = *position;
+    let mut x = 0u64;
+    for shift in 0..10 {
+        let byte = *content.get(*position)
+            .ok_or(Error::UnexpectedEof { offset: Offset::Byte(*position) })?;
+        *position += 1;
+        if shift == 9 && byte > 1 {
+            break;
+        }
+        x |= ((byte & 0x7f) as u64) << (7 * shift);
+        if byte & 0x80 == 0 {
+            return Ok(x);
+        }
+    }
+    Err(Error::InvalidHeader { offset: Offset::Byte(start) })
+}
+
+#[test]
+fn varint_testing() {
+    let values = [0, 1, 127, 128, 300, 4096, 1 << 32, u64::MAX];
+    let mut output = Vec::new();
+    for &x in &values {
+        write_varint(&mut output, x);
+    }
+    assert_eq!(&output[..5], &[0, 1, 127, 0x80, 0x01]);
+
+    let mut position = 0;
+    for &x in &values {
+        assert_eq!(read_varint(&output, &mut position), Ok(x));
+    }
+    assert_eq!(position, output.len());
+    assert_eq!(read_varint(&output, &mut position),
+               Err(Error::UnexpectedEof { offset: Offset::Byte(output.len()) }));
+    assert_eq!(read_varint(&[0xff; 11], &mut 0),
+               Err(Error::InvalidHeader { offset: Offset::Byte(0) }));
+}
@@ -0,0 +1,102 @@
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Checksum {
+    Disabled,
+    #[default]
+    Crc32,
+    Adler32,
+}
+
+impl Checksum {
+    pub fn id(self) -> u8 {
+        match self {
+            Checksum::Disabled => 0,
+            Checksum::Crc32 => 1,
+            Checksum::Adler32 => 2,
+        }
+    }
+
+    pub fn from_id(id: u8) -> Option<Checksum> {
+        match id {
+            0 => Some(Checksum::Disabled),
+            1 => Some(Checksum::Crc32),
+            2 => Some(Checksum::Adler32),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Checksum::Disabled => "none",
+            Checksum::Crc32 => "crc32",
+            Checksum::Adler32 => "adler32",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Checksum> {
+        match name {
+            "none" => Some(Checksum::Disabled),
+            "crc32" => Some(Checksum::Crc32),
+            "adler32" => Some(Checksum::Adler32),
+            _ => None,
+        }
+    }
+
+    /// Number of trailer bytes this checksum takes in a container.
+    pub fn size(self) -> usize {
+        match self {
+            Checksum::Disabled => 0,
+            Checksum::Crc32 | Checksum::Adler32 => 4,
+        }
+    }
+
+    pub fn compute(self, content: &[u8]) -> u32 {
+        match self {
+            Checksum::Disabled => 0,
+            Checksum::Crc32 => crc32(content),
+            Checksum::Adler32 => adler32(content),
+        }
+    }
+}
+
+pub fn crc32(content: &[u8]) -> u32 {
+    let mut table = [0u32; 256];
+    for (i, entry) in table.iter_mut().enumerate() {
+        let mut x = i as u32;
+        for _ in 0..8 {
+            x = if x & 1 == 1 { 0xedb8_8320 ^ (x >> 1) } else { x >> 1 };
+        }
+        *entry = x;
+    }
+
+    let mut crc = !0u32;
+    for &byte in content {
+        crc = table[((crc ^ byte as u32) & 0xff) as usize] ^ (crc >> 8);
+    }
+    !crc
+}
+
+pub fn adler32(content: &[u8]) -> u32 {
+    const MOD_ADLER: u32 = 65521;
+    let (mut a, mut b) = (1u32, 0u32);
+    // 5552 is the largest block for which b cannot overflow before the reduction
+    for chunk in content.chunks(5552) {
+        for &byte in chunk {
+            a += byte as u32;
+            b += a;
+        }
+        a %= MOD_ADLER;
+        b %= MOD_ADLER;
+    }
+    (b << 16) | a
+}
+
+#[test]
+fn checksum_testing() {
+    assert_eq!(crc32(b""), 0);
+    assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+    assert_eq!(crc32(b"The quick brown fox jumps over the lazy dog"),
+               0x414f_a339);
+    assert_eq!(adler32(b""), 1);
+    assert_eq!(adler32(b"Wikipedia"), 0x11e6_0398);
+    assert_eq!(adler32(&vec![0xff; 100_000]), 0x149a_302c);
+}
@@ -0,0 +1,437 @@
+use error::Error;
+use huffman::HuffmanParams;
+use lz_77::{Lz77Params, LzssParams};
+use lz_78::Lz78Params;
+use lzw::LzwParams;
+use ppm::PpmParams;
+use bwt::BwtParams;
+use filter::{FilterKind, FilterParams};
+use std::rc::Rc;
+use super::{compression_huffman_with, decompression_huffman, compression_adaptive_huffman,
+            decompression_adaptive_huffman, decompression_lz77,
+            compression_lz78_with, decompression_lz78, compression_lz77_with, compression_lzss_with,
+            decompression_lzss, compression_lzw_with, decompression_lzw, compression_arithmetic,
+            decompression_arithmetic, compression_adaptive_arithmetic,
+            decompression_adaptive_arithmetic, compression_rans, decompression_rans,
+            compression_tans, decompression_tans, compression_ppm_with, decompression_ppm,
+            compression_bwt_with, decompression_bwt, compression_mtf, decompression_mtf,
+            compression_packbits, decompression_packbits, compression_escape_rle,
+            decompression_escape_rle, compression_zero_runs, decompression_zero_runs,
+            compression_filter_with, decompression_filter};
+
+pub trait Codec {
+    fn encode(&self, content: &[u8]) -> Vec<u8>;
+    fn decode(&self, content: &[u8]) -> Result<Vec<u8>, Error>;
+}
+
+#[derive(Default)]
+pub struct Huffman {
+    pub params: HuffmanParams,
+}
+
+impl Codec for Huffman {
+    fn encode(&self, content: &[u8]) -> Vec<u8> {
+        compression_huffman_with(content, self.params)
+    }
+
+    fn decode(&self, content: &[u8]) -> Result<Vec<u8>, Error> {
+        decompression_huffman(content)
+    }
+}
+
+pub struct AdaptiveHuffman;
+
+impl Codec for AdaptiveHuffman {
+    fn encode(&self, content: &[u8]) -> Vec<u8> {
+        compression_adaptive_huffman(content.iter().cloned())
+    }
+
+    fn decode(&self, content: &[u8]) -> Result<Vec<u8>, Error> {
+        decompression_adaptive_huffman(content)
+    }
+}
+
+pub struct Arithmetic;
+
+impl Codec for Arithmetic {
+    fn encode(&self, content: &[u8]) -> Vec<u8> {
+        compression_arithmetic(content)
+    }
+
+    fn decode(&self, content: &[u8]) -> Result<Vec<u8>, Error> {
+        decompression_arithmetic(content)
+    }
+}
+
+pub struct AdaptiveArithmetic;
+
+impl Codec for AdaptiveArithmetic {
+    fn encode(&self, content: &[u8]) -> Vec<u8> {
+        compression_adaptive_arithmetic(content.iter().cloned())
+    }
+
+    fn decode(&self, content: &[u8]) -> Result<Vec<u8>, Error> {
+        decompression_adaptive_arithmetic(content)
+    }
+}
+
+pub struct Rans;
+
+impl Codec for Rans {
+    fn encode(&self, content: &[u8]) -> Vec<u8> {
+        compression_rans(content)
+    }
+
+    fn decode(&self, content: &[u8]) -> Result<Vec<u8>, Error> {
+        decompression_rans(content)
+    }
+}
+
+pub struct Tans;
+
+impl Codec for Tans {
+    fn encode(&self, content: &[u8]) -> Vec<u8> {
+        compression_tans(content)
+    }
+
+    fn decode(&self, content: &[u8]) -> Result<Vec<u8>, Error> {
+        decompression_tans(content)
+    }
+}
+
+#[derive(Default)]
+pub struct Ppm {
+    pub params: PpmParams,
+}
+
+impl Codec for Ppm {
+    fn encode(&self, content: &[u8]) -> Vec<u8> {
+        compression_ppm_with(content, self.params)
+    }
+
+    fn decode(&self, content: &[u8]) -> Result<Vec<u8>, Error> {
+        decompression_ppm(content)
+    }
+}
+
+#[derive(Default)]
+pub struct Bwt {
+    pub params: BwtParams,
+}
+
+impl Codec for Bwt {
+    fn encode(&self, content: &[u8]) -> Vec<u8> {
+        compression_bwt_with(content, self.params)
+    }
+
+    fn decode(&self, content: &[u8]) -> Result<Vec<u8>, Error> {
+        decompression_bwt(content)
+    }
+}
+
+pub struct Mtf;
+
+impl Codec for Mtf {
+    fn encode(&self, content: &[u8]) -> Vec<u8> {
+        compression_mtf(content)
+    }
+
+    fn decode(&self, content: &[u8]) -> Result<Vec<u8>, Error> {
+        decompression_mtf(content)
+    }
+}
+
+pub struct PackBits;
+
+impl Codec for PackBits {
+    fn encode(&self, content: &[u8]) -> Vec<u8> {
+        compression_packbits(content)
+    }
+
+    fn decode(&self, content: &[u8]) -> Result<Vec<u8>, Error> {
+        decompression_packbits(content)
+    }
+}
+
+pub struct EscapeRle;
+
+impl Codec for EscapeRle {
+    fn encode(&self, content: &[u8]) -> Vec<u8> {
+        compression_escape_rle(content)
+    }
+
+    fn decode(&self, content: &[u8]) -> Result<Vec<u8>, Error> {
+        decompression_escape_rle(content)
+    }
+}
+
+pub struct ZeroRuns;
+
+impl Codec for ZeroRuns {
+    fn encode(&self, content: &[u8]) -> Vec<u8> {
+        compression_zero_runs(content)
+    }
+
+    fn decode(&self, content: &[u8]) -> Result<Vec<u8>, Error> {
+        decompression_zero_runs(content)
+    }
+}
+
+#[derive(Default)]
+pub struct Filter {
+    pub params: FilterParams,
+}
+
+impl Codec for Filter {
+    fn encode(&self, content: &[u8]) -> Vec<u8> {
+        compression_filter_with(content, self.params)
+    }
+
+    fn decode(&self, content: &[u8]) -> Result<Vec<u8>, Error> {
+        decompression_filter(content)
+    }
+}
+
+#[derive(Default)]
+pub struct Lz77 {
+    pub params: Lz77Params,
+}
+
+impl Codec for Lz77 {
+    fn encode(&self, content: &[u8]) -> Vec<u8> {
+        compression_lz77_with(content.iter().cloned(), self.params)
+    }
+
+    fn decode(&self, content: &[u8]) -> Result<Vec<u8>, Error> {
+        decompression_lz77(content)
+    }
+}
+
+#[derive(Default)]
+pub struct Lzss {
+    pub params: LzssParams,
+}
+
+impl Codec for Lzss {
+    fn encode(&self, content: &[u8]) -> Vec<u8> {
+        compression_lzss_with(content.iter().cloned(), self.params)
+    }
+
+    fn decode(&self, content: &[u8]) -> Result<Vec<u8>, Error> {
+        decompression_lzss(content)
+    }
+}
+
+#[derive(Default)]
+pub struct Lz78 {
+    pub params: Lz78Params,
+}
+
+impl Codec for Lz78 {
+    fn encode(&self, content: &[u8]) -> Vec<u8> {
+        compression_lz78_with(content, self.params)
+    }
+
+    fn decode(&self, content: &[u8]) -> Result<Vec<u8>, Error> {
+        decompression_lz78(content)
+    }
+}
+
+#[derive(Default)]
+pub struct Lzw {
+    pub params: LzwParams,
+}
+
+impl Codec for Lzw {
+    fn encode(&self, content: &[u8]) -> Vec<u8> {
+        compression_lzw_with(content.iter().cloned(), self.params)
+    }
+
+    fn decode(&self, content: &[u8]) -> Result<Vec<u8>, Error> {
+        decompression_lzw(content)
+    }
+}
+
+#[derive(Clone)]
+struct Stage {
+    id: u8,
+    name: String,
+    codec: Rc<dyn Codec>,
+}
+
+/// Codecs applied one after the other; decoding runs them in reverse order.
+/// An empty chain stores its input as is.
+#[derive(Clone, Default)]
+pub struct Chain {
+    stages: Vec<Stage>,
+}
+
+impl Chain {
+    pub fn new() -> Chain {
+        Chain::default()
+    }
+
+    /// Container ids of the stages, in encoding order.
+    pub fn ids(&self) -> Vec<u8> {
+        self.stages.iter().map(|stage| stage.id).collect()
+    }
+
+    /// Stage names joined by `+`, or `store` for the empty chain.
+    pub fn name(&self) -> String {
+        if self.stages.is_empty() {
+            return "store".to_string();
+        }
+        let names: Vec<&str> = self.stages.iter().map(|stage| stage.name.as_str()).collect();
+        names.join("+")
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.stages.is_empty()
+    }
+}
+
+impl Codec for Chain {
+    fn encode(&self, content: &[u8]) -> Vec<u8> {
+        let mut data = content.to_vec();
+        for stage in &self.stages {
+            data = stage.codec.encode(&data);
+        }
+        data
+    }
+
+    fn decode(&self, content: &[u8]) -> Result<Vec<u8>, Error> {
+        let mut data = content.to_vec();
+        for stage in self.stages.iter().rev() {
+            data = stage.codec.decode(&data)?;
+        }
+        Ok(data)
+    }
+}
+
+/// Maps codec names (for users) and ids (for the container) to codecs.
+pub struct Registry {
+    stages: Vec<Stage>,
+}
+
+impl Default for Registry {
+    fn default() -> Registry {
+        let mut registry = Registry::new();
+        registry.register(1, "huffman", Huffman::default());
+        registry.register(2, "lz77", Lz77::default());
+        registry.register(3, "lz78", Lz78::default());
+        registry.register(4, "lzss", Lzss::default());
+        registry.register(5, "adaptive-huffman", AdaptiveHuffman);
+        registry.register(6, "lzw", Lzw::default());
+        registry.register(7, "arithmetic", Arithmetic);
+        registry.register(8, "adaptive-arithmetic", AdaptiveArithmetic);
+        registry.register(9, "rans", Rans);
+        registry.register(10, "tans", Tans);
+        registry.register(11, "ppm", Ppm::default());
+        registry.register(12, "bwt", Bwt::default());
+        registry.register(13, "mtf", Mtf);
+        registry.register(14, "packbits", PackBits);
+        registry.register(15, "escape-rle", EscapeRle);
+        registry.register(16, "zero-runs", ZeroRuns);
+        registry.register(17, "delta", Filter::default());
+        for (id, &stride) in (18..).zip(&[2, 4, 8]) {
+            let params = FilterParams::new(FilterKind::Delta, stride);
+            registry.register(id, &format!("delta-{}", stride), Filter { params });
+        }
+        registry.register(21, "xor", Filter { params: FilterParams::new(FilterKind::Xor, 1) });
+        registry
+    }
+}
+
+impl Registry {
+    /// An empty registry; `Registry::default()` has the codecs of this crate.
+    pub fn new() -> Registry {
+        Registry { stages: Vec::new() }
+    }
+
+    pub fn register<C: Codec + 'static>(&mut self, id: u8, name: &str, codec: C) {
+        assert!(!name.is_empty() && !name.contains('+') && name != "store",
+                "invalid codec name '{}'",
+                name);
+        assert!(self.stages.iter().all(|stage| stage.id != id && stage.name != name),
+                "codec '{}' (id {}) is already registered",
+                name,
+                id);
+        self.stages.push(Stage {
+            id,
+            name: name.to_string(),
+            codec: Rc::new(codec),
+        });
+    }
+
+    pub fn names(&self) -> Vec<&str> {
+        self.stages.iter().map(|stage| stage.name.as_str()).collect()
+    }
+
+    pub fn name(&self, id: u8) -> Option<&str> {
+        self.stages.iter().find(|stage| stage.id == id).map(|stage| stage.name.as_str())
+    }
+
+    /// Builds a chain from names joined by `+`, e.g. `lz77+huffman`; `store` is the empty
+    /// chain. On failure, returns the unknown name.
+    pub fn chain(&self, names: &str) -> Result<Chain, String> {
+        if names == "store" {
+            return Ok(Chain::new());
+        }
+        let mut chain = Chain::new();
+        for name in names.split('+') {
+            match self.stages.iter().find(|stage| stage.name == name) {
+                Some(stage) => chain.stages.push(stage.clone()),
+                None => return Err(name.to_string()),
+            }
+        }
+        Ok(chain)
+    }
+
+    /// Builds a chain from container ids. On failure, returns the index of the unknown id.
+    pub fn chain_from_ids(&self, ids: &[u8]) -> Result<Chain, usize> {
+        let mut chain = Chain::new();
+        for (index, &id) in ids.iter().enumerate() {
+            match self.stages.iter().find(|stage| stage.id == id) {
+                Some(stage) => chain.stages.push(stage.clone()),
+                None => return Err(index),
+            }
+        }
+        Ok(chain)
+    }
+}
+
+#[test]
+fn codec_testing() {
+    struct Reverse;
+    impl Codec for Reverse {
+        fn encode(&self, content: &[u8]) -> Vec<u8> {
+            content.iter().rev().cloned().collect()
+        }
+        fn decode(&self, content: &[u8]) -> Result<Vec<u8>, Error> {
+            Ok(content.iter().rev().cloned().collect())
+        }
+    }
+
+    let mut registry = Registry::default();
+    registry.register(200, "reverse", Reverse);
+    assert_eq!(registry.name(200), Some("reverse"));
+
+    let input = "abracadabra abracadabra abracadabra".as_bytes().to_vec();
+    for names in &["store", "huffman", "lz77", "lz78", "lz77+huffman", "reverse+lz78+huffman",
+                   "lzss+adaptive-huffman", "lzw+huffman",
+                   "lz77+arithmetic", "lz78+adaptive-arithmetic",
+                   "lz77+rans", "lzss+tans", "ppm", "bwt",
+                   "mtf+huffman", "packbits+huffman", "escape-rle+huffman",
+                   "mtf+zero-runs+huffman", "delta+huffman", "delta-8+lz77", "xor+delta-2"] {
+        let chain = registry.chain(names).unwrap();
+        assert_eq!(&chain.name(), names);
+        let coded = chain.encode(&input);
+        assert_eq!(input, chain.decode(&coded).unwrap());
+
+        let same = registry.chain_from_ids(&chain.ids()).unwrap();
+        assert_eq!(input, same.decode(&coded).unwrap());
+    }
+
+    assert_eq!(registry.chain("lz77+zip").err(), Some("zip".to_string()));
+    assert_eq!(registry.chain_from_ids(&[2, 99]).err(), Some(1));
+}
@@ -0,0 +1,127 @@
+//! `.lm` container: a small header in front of every stream produced by `compress`.
+//!
+//! Layout (all integers little endian):
+//!
+//! ```text
+//! magic (4) | version (1) | stage count (1) | stage ids (n) | original length (8) |
+//! checksum id (1) | payload | checksum of the original data (0 or 4)
+//! ```
+//!
+//! Stage ids are resolved to codecs by a `Registry`.
+
+use checksum::Checksum;
+use error::{Error, Offset};
+
+pub const MAGIC: &[u8; 4] = b"\x89LM\x1a";
+pub const FORMAT_VERSION: u8 = 7;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Header {
+    pub version: u8,
+    /// Registry ids of the stages, in the order they were applied by the compressor.
+    pub pipeline: Vec<u8>,
+    pub original_len: u64,
+    pub checksum: Checksum,
+}
+
+impl Header {
+    pub fn new(pipeline: &[u8], original_len: u64, checksum: Checksum) -> Header {
+        Header {
+            version: FORMAT_VERSION,
+            pipeline: pipeline.to_vec(),
+            original_len,
+            checksum,
+        }
+    }
+
+    pub fn write(&self, output: &mut Vec<u8>) {
+        assert!(self.pipeline.len() < 256, "pipeline too long");
+        output.extend_from_slice(MAGIC);
+        output.push(self.version);
+        output.push(self.pipeline.len() as u8);
+        output.extend_from_slice(&self.pipeline);
+        for i in 0..8 {
+            output.push((self.original_len >> (8 * i)) as u8);
+        }
+        output.push(self.checksum.id());
+    }
+
+    /// Parses the header and returns it together with the remaining payload, checksum
+    /// trailer included.
+    pub fn read(content: &[u8]) -> Result<(Header, &[u8]), Error> {
+        if content.len() < MAGIC.len() || &content[..MAGIC.len()] != MAGIC {
+            return Err(Error::InvalidMagic);
+        }
+        let mut position = MAGIC.len();
+        let mut next = || {
+            let byte = *content.get(position)
+                .ok_or(Error::UnexpectedEof { offset: Offset::Byte(position) })?;
+            position += 1;
+            Ok((byte, Offset::Byte(position - 1)))
+        };
+
+        let (version, _) = next()?;
+        if version != FORMAT_VERSION {
+            return Err(Error::UnsupportedVersion { version });
+        }
+
+        let (stages, _) = next()?;
+        let mut pipeline = Vec::new();
+        for _ in 0..stages {
+            pipeline.push(next()?.0);
+        }
+
+        let mut original_len = 0u64;
+        for i in 0..8 {
+            let (byte, _) = next()?;
+            original_len |= (byte as u64) << (8 * i);
+        }
+
+        let (id, offset) = next()?;
+        let checksum = Checksum::from_id(id).ok_or(Error::UnknownChecksum { id, offset })?;
+
+        let header = Header {
+            version,
+            pipeline,
+            original_len,
+            checksum,
+        };
+        Ok((header, &content[position..]))
+    }
+
+    /// Offset of the id of stage `index` in a serialized header.
+    pub fn stage_offset(index: usize) -> Offset {
+        Offset::Byte(MAGIC.len() + 2 + index)
+    }
+
+    /// Offset of the original length in the serialized header.
+    pub fn length_offset(&self) -> Offset {
+        Offset::Byte(MAGIC.len() + 2 + self.pipeline.len())
+    }
+}
+
+#[test]
+fn header_testing() {
+    let header = Header::new(&[2, 1], 123456789, Checksum::Adler32);
+    let mut output = Vec::new();
+    header.write(&mut output);
+    output.extend_from_slice(&[1, 2, 3]);
+
+    let (decoded, payload) = Header::read(&output).unwrap();
+    assert_eq!(header, decoded);
+    assert_eq!(payload, &[1, 2, 3]);
+
+    assert_eq!(Header::read(b""), Err(Error::InvalidMagic));
+    assert_eq!(Header::read(b"not a container"), Err(Error::InvalidMagic));
+    assert_eq!(Header::read(&output[..6]),
+               Err(Error::UnexpectedEof { offset: Offset::Byte(6) }));
+    assert_eq!(Header::read(&output[..16]),
+               Err(Error::UnexpectedEof { offset: Offset::Byte(16) }));
+
+    output[16] = 200;
+    assert_eq!(Header::read(&output),
+               Err(Error::UnknownChecksum {
+                   id: 200,
+                   offset: Offset::Byte(16),
+               }));
+}
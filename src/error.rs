@@ -0,0 +1,84 @@
+use std::error;
+use std::fmt;
+
+/// Where in its input a decoder stopped. Offsets are relative to the input of the stage
+/// that failed, not to the whole `.lm` file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Offset {
+    Bit(usize),
+    Byte(usize),
+    /// Index of a decoded token (e.g. an LZ78 pair) when no stream position is known.
+    Token(usize),
+}
+
+impl fmt::Display for Offset {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Offset::Bit(offset) => write!(f, "bit {}", offset),
+            Offset::Byte(offset) => write!(f, "byte {}", offset),
+            Offset::Token(index) => write!(f, "token {}", index),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// The stream ended in the middle of a header, code or token.
+    UnexpectedEof { offset: Offset },
+    /// A serialized Huffman tree is malformed.
+    InvalidTree { offset: Offset },
+    /// A pointer refers to data or a dictionary entry that does not exist.
+    InvalidBackReference { offset: Offset },
+    /// A stage header holds impossible parameters.
+    InvalidHeader { offset: Offset },
+    /// The input does not start with the `.lm` magic number.
+    InvalidMagic,
+    UnsupportedVersion { version: u8 },
+    UnknownAlgorithm { id: u8, offset: Offset },
+    UnknownChecksum { id: u8, offset: Offset },
+    /// The stream decoded, but not to as many bytes as were compressed. The offset is the
+    /// one of the length field.
+    LengthMismatch { expected: u64, found: u64, offset: Offset },
+    /// The stream decoded, but not to the data that was compressed. The offset is the one of
+    /// the checksum trailer.
+    ChecksumMismatch { expected: u32, found: u32, offset: Offset },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::UnexpectedEof { offset } => write!(f, "unexpected end of stream at {}", offset),
+            Error::InvalidTree { offset } => write!(f, "invalid Huffman tree at {}", offset),
+            Error::InvalidBackReference { offset } => {
+                write!(f, "invalid back reference at {}", offset)
+            }
+            Error::InvalidHeader { offset } => write!(f, "invalid parameters at {}", offset),
+            Error::InvalidMagic => write!(f, "not a .lm stream (bad magic number)"),
+            Error::UnsupportedVersion { version } => {
+                write!(f, "unsupported format version {}", version)
+            }
+            Error::UnknownAlgorithm { id, offset } => {
+                write!(f, "unknown algorithm id {} at {}", id, offset)
+            }
+            Error::UnknownChecksum { id, offset } => {
+                write!(f, "unknown checksum id {} at {}", id, offset)
+            }
+            Error::LengthMismatch { expected, found, offset } => {
+                write!(f,
+                       "length mismatch at {}: expected {} bytes, found {}",
+                       offset,
+                       expected,
+                       found)
+            }
+            Error::ChecksumMismatch { expected, found, offset } => {
+                write!(f,
+                       "checksum mismatch at {}: expected {:08x}, found {:08x}",
+                       offset,
+                       expected,
+                       found)
+            }
+        }
+    }
+}
+
+impl error::Error for Error {}
@@ -0,0 +1,86 @@
+//! Reversible filters for numeric data, to place before the other codecs: each byte is
+//! replaced by its difference (or XOR) with the byte `stride` places before it, so that
+//! slowly varying little-endian samples of `stride` bytes turn into runs of small values.
+
+pub const STRIDES: [usize; 4] = [1, 2, 4, 8];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FilterKind {
+    Delta,
+    Xor,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FilterParams {
+    pub kind: FilterKind,
+    /// Width in bytes of the samples: 1, 2, 4 or 8.
+    pub stride: usize,
+}
+
+impl Default for FilterParams {
+    fn default() -> FilterParams {
+        FilterParams {
+            kind: FilterKind::Delta,
+            stride: 1,
+        }
+    }
+}
+
+impl FilterParams {
+    pub fn new(kind: FilterKind, stride: usize) -> FilterParams {
+        let params = FilterParams { kind, stride };
+        assert!(params.is_valid(), "invalid filter parameters {:?}", params);
+        params
+    }
+
+    pub fn is_valid(&self) -> bool {
+        STRIDES.contains(&self.stride)
+    }
+}
+
+pub fn filter_encode(content: &[u8], params: FilterParams) -> Vec<u8> {
+    assert!(params.is_valid(), "invalid filter parameters {:?}", params);
+    let stride = params.stride;
+    let mut output = content[..stride.min(content.len())].to_vec();
+    for (i, &byte) in content.iter().enumerate().skip(stride) {
+        output.push(match params.kind {
+            FilterKind::Delta => byte.wrapping_sub(content[i - stride]),
+            FilterKind::Xor => byte ^ content[i - stride],
+        });
+    }
+    output
+}
+
+pub fn filter_decode(content: &[u8], params: FilterParams) -> Vec<u8> {
+    assert!(params.is_valid(), "invalid filter parameters {:?}", params);
+    let stride = params.stride;
+    let mut output = content[..stride.min(content.len())].to_vec();
+    for (i, &byte) in content.iter().enumerate().skip(stride) {
+        let previous = output[i - stride];
+        output.push(match params.kind {
+            FilterKind::Delta => byte.wrapping_add(previous),
+            FilterKind::Xor => byte ^ previous,
+        });
+    }
+    output
+}
+
+#[test]
+fn filter_testing() {
+    let params = FilterParams::default();
+    assert_eq!(filter_encode(&[10, 12, 11, 11, 0], params), vec![10, 2, 255, 0, 245]);
+    let params = FilterParams::new(FilterKind::Xor, 2);
+    assert_eq!(filter_encode(&[1, 2, 3, 2, 3], params), vec![1, 2, 2, 0, 0]);
+
+    let input: Vec<u8> = (0..1000u32).flat_map(|i| (i * i).to_le_bytes()).collect();
+    for &kind in &[FilterKind::Delta, FilterKind::Xor] {
+        for &stride in &STRIDES {
+            let params = FilterParams::new(kind, stride);
+            for len in 0..10 {
+                assert_eq!(&input[..len], &filter_decode(&filter_encode(&input[..len], params),
+                                                         params)[..]);
+            }
+            assert_eq!(input, filter_decode(&filter_encode(&input, params), params));
+        }
+    }
+}
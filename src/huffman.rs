@@ -1,8 +1,52 @@
-use bit_vec::{BitVec, Iter};
+use bit_vec::BitVec;
 use bitvec_util::*;
 use binary_heap_compare::BinaryHeapCompare;
+use error::{Error, Offset};
+use std::cmp;
 use std::collections::BTreeMap;
-use std;
+
+/// Longer codes are rejected: no Huffman tree over at most 2^64 occurrences is that deep,
+/// and canonical code values then fit in a `u128`.
+const MAX_CODE_LENGTH: u8 = 127;
+
+pub const BLOCK_SIZE: usize = 1 << 16;
+
+/// `compression_huffman` builds a code for each block of `block_size` bytes, or reuses the
+/// previous one when that is cheaper. Codes are at most `max_code_length` bits long if given.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HuffmanParams {
+    pub block_size: usize,
+    pub max_code_length: Option<u8>,
+}
+
+impl Default for HuffmanParams {
+    fn default() -> HuffmanParams {
+        HuffmanParams {
+            block_size: BLOCK_SIZE,
+            max_code_length: None,
+        }
+    }
+}
+
+impl HuffmanParams {
+    pub fn new(block_size: usize, max_code_length: Option<u8>) -> HuffmanParams {
+        let params = HuffmanParams {
+            block_size,
+            max_code_length,
+        };
+        assert!(params.is_valid(),
+                "invalid Huffman parameters: block {}, max code length {:?}",
+                block_size,
+                max_code_length);
+        params
+    }
+
+    /// Limited codes must still cover the 256 byte values.
+    pub fn is_valid(&self) -> bool {
+        self.block_size >= 1 &&
+        self.max_code_length.is_none_or(|max| (8..=MAX_CODE_LENGTH).contains(&max))
+    }
+}
 
 #[derive(Debug)]
 pub enum Node<T> {
@@ -55,7 +99,7 @@ impl<T: Ord + Copy> Node<T> {
     }
 
     #[allow(dead_code)]
-    pub fn scan(&self, iter: &mut Iter) -> Result<Option<T>, &'static str> {
+    pub fn scan(&self, iter: &mut BitReader) -> Result<Option<T>, Error> {
         match *self {
             Node::Leaf(character) => Ok(Some(character)),
             Node::Branch(ref node_0, ref node_1) => {
@@ -63,7 +107,9 @@ impl<T: Ord + Copy> Node<T> {
                     let result = if bit { node_1 } else { node_0 }.scan(iter)?;
                     match result {
                         Some(character) => Ok(Some(character)),
-                        None => Err("unexpected EOF"),
+                        None => {
+                            Err(Error::UnexpectedEof { offset: Offset::Bit(iter.offset()) })
+                        }
                     }
                 } else {
                     Ok(None)
@@ -71,93 +117,507 @@ impl<T: Ord + Copy> Node<T> {
             }
         }
     }
-}
 
-pub trait IntoBitVec {
-    fn encode(&self) -> BitVec;
+    fn height(&self) -> u32 {
+        match *self {
+            Node::Leaf(_) => 0,
+            Node::Branch(ref left, ref right) => 1 + cmp::max(left.height(), right.height()),
+        }
+    }
+
+    /// Like `from_statistics`, with codes of at most `max_length` bits.
+    #[allow(dead_code)]
+    pub fn from_statistics_limited(statistics: &BTreeMap<T, u64>, max_length: u8) -> Node<T> {
+        let lengths = limited_code_lengths(statistics, max_length);
+        if lengths.len() == 1 {
+            return Node::Leaf(*lengths.keys().next().unwrap());
+        }
+        Node::from_code_lengths(&lengths).unwrap()
+    }
+
+    /// Depth of every leaf, i.e. the length of its code.
+    pub fn code_lengths(&self) -> BTreeMap<T, u8> {
+        let mut lengths = BTreeMap::new();
+        self.collect_lengths(0, &mut lengths);
+        lengths
+    }
+
+    fn collect_lengths(&self, depth: u8, lengths: &mut BTreeMap<T, u8>) {
+        match *self {
+            Node::Leaf(symbol) => {
+                lengths.insert(symbol, depth);
+            }
+            Node::Branch(ref left, ref right) => {
+                left.collect_lengths(depth + 1, lengths);
+                right.collect_lengths(depth + 1, lengths);
+            }
+        }
+    }
+
+    /// Tree of the canonical code with these lengths (0 means no code), or `None` if they do
+    /// not describe a complete prefix code.
+    pub fn from_code_lengths(lengths: &BTreeMap<T, u8>) -> Option<Node<T>> {
+        let codes = canonical(lengths)?;
+        Self::from_codes(&codes, 0)
+    }
+
+    // `codes` are in canonical order, which sorts them as bit strings
+    fn from_codes(codes: &[(T, u8, u128)], depth: u8) -> Option<Node<T>> {
+        let &(symbol, length, _) = codes.first()?;
+        if length == depth {
+            return if codes.len() == 1 { Some(Node::Leaf(symbol)) } else { None };
+        }
+        let split = codes.iter()
+            .position(|&(_, length, code)| code >> (length - depth - 1) & 1 == 1)
+            .unwrap_or(codes.len());
+        Some(Node::Branch(Box::new(Self::from_codes(&codes[..split], depth + 1)?),
+                          Box::new(Self::from_codes(&codes[split..], depth + 1)?)))
+    }
 }
 
-impl IntoBitVec for u8 {
-    fn encode(&self) -> BitVec {
-        BitVec::from_bytes(&[*self])
+/// Symbols sorted by code length then value, with their canonical code: each code is the
+/// previous one plus one, shifted left when the length grows. `None` if some length is too
+/// long or the lengths over-subscribe the code space.
+fn canonical<T: Ord + Copy>(lengths: &BTreeMap<T, u8>) -> Option<Vec<(T, u8, u128)>> {
+    let mut symbols: Vec<(u8, T)> = lengths.iter()
+        .filter(|&(_, &length)| length > 0)
+        .map(|(&symbol, &length)| (length, symbol))
+        .collect();
+    symbols.sort();
+
+    let mut codes = Vec::with_capacity(symbols.len());
+    let (mut code, mut previous) = (0u128, 0u8);
+    for (length, symbol) in symbols {
+        if length > MAX_CODE_LENGTH {
+            return None;
+        }
+        code <<= length - previous;
+        if code >> length != 0 {
+            return None;
+        }
+        codes.push((symbol, length, code));
+        code += 1;
+        previous = length;
     }
+    Some(codes)
 }
 
-impl IntoBitVec for u64 {
-    fn encode(&self) -> BitVec {
-        let mut x = *self;
-        let mut xs = [0; 8];
-        for i in 0..8 {
-            xs[7 - i] = (x & 0xff) as u8;
-            x >>= 8;
+pub fn canonical_codes<T: Ord + Copy>(lengths: &BTreeMap<T, u8>) -> BTreeMap<T, BitVec> {
+    let codes = canonical(lengths).expect("invalid code lengths");
+    let mut dictionnary = BTreeMap::new();
+    for (symbol, length, code) in codes {
+        let mut word = BitVec::new();
+        for i in (0..length).rev() {
+            word.push(code >> i & 1 == 1);
         }
-        BitVec::from_bytes(&xs)
+        dictionnary.insert(symbol, word);
+    }
+    dictionnary
+}
+
+/// Alphabets whose code lengths can be written in a header, one entry per index from 0.
+pub trait Symbol: Ord + Copy {
+    fn index(self) -> u64;
+    fn from_index(index: u64) -> Option<Self>;
+
+    /// The symbol after this one, or before it at the end of the alphabet.
+    fn neighbor(self) -> Self {
+        self.index()
+            .checked_add(1)
+            .and_then(Self::from_index)
+            .or_else(|| Self::from_index(self.index() - 1))
+            .unwrap()
+    }
+}
+
+impl Symbol for u8 {
+    fn index(self) -> u64 {
+        self as u64
+    }
+
+    fn from_index(index: u64) -> Option<u8> {
+        if index < 256 { Some(index as u8) } else { None }
     }
 }
 
-pub trait FromBitVec
-    where Self: std::marker::Sized
-{
-    fn decode(&mut Iter) -> Result<Self, &'static str>;
+impl Symbol for u16 {
+    fn index(self) -> u64 {
+        self as u64
+    }
+
+    fn from_index(index: u64) -> Option<u16> {
+        if index < 1 << 16 { Some(index as u16) } else { None }
+    }
+}
+
+impl Symbol for u64 {
+    fn index(self) -> u64 {
+        self
+    }
+
+    fn from_index(index: u64) -> Option<u64> {
+        Some(index)
+    }
+}
+
+/// Huffman code lengths for the symbols occurring in `statistics`, at most `max_length` bits
+/// long if given. A lone symbol gets a partner so that it still has a one bit code.
+pub fn code_lengths_from_statistics<T: Symbol>(statistics: &BTreeMap<T, u64>,
+                                               max_length: Option<u8>)
+                                               -> BTreeMap<T, u8> {
+    let mut statistics = statistics.clone();
+    if statistics.len() == 1 {
+        let index = if statistics.keys().next().unwrap().index() == 0 { 1 } else { 0 };
+        statistics.insert(T::from_index(index).unwrap(), 1);
+    }
+    match max_length {
+        Some(max_length) => limited_code_lengths(&statistics, max_length),
+        None => Node::from_statistics(&statistics).code_lengths(),
+    }
+}
+
+/// Optimal code lengths of at most `max_length` bits, computed by package-merge. Panics if
+/// `max_length` is 0 or too short to give distinct codes to the symbols occurring.
+pub fn limited_code_lengths<T: Ord + Copy>(statistics: &BTreeMap<T, u64>,
+                                           max_length: u8)
+                                           -> BTreeMap<T, u8> {
+    let leaves: Vec<(u64, T)> = {
+        let mut leaves: Vec<(u64, T)> = statistics.iter()
+            .filter(|&(_, &count)| count != 0)
+            .map(|(&symbol, &count)| (count, symbol))
+            .collect();
+        leaves.sort();
+        leaves
+    };
+    let mut lengths: BTreeMap<T, u8> = leaves.iter().map(|&(_, symbol)| (symbol, 0)).collect();
+    if leaves.len() < 2 {
+        return lengths;
+    }
+    assert!(max_length > 0 && max_length <= MAX_CODE_LENGTH &&
+            (max_length >= 64 || leaves.len() as u64 <= 1 << max_length),
+            "{} symbols do not fit in codes of {} bits",
+            leaves.len(),
+            max_length);
+
+    // lists[i] merges the leaves with the packages of adjacent pairs of lists[i - 1]; an item
+    // is a leaf index or None for the next package, packages being taken in order
+    let mut lists: Vec<Vec<(u128, Option<usize>)>> =
+        vec![leaves.iter().enumerate().map(|(i, &(count, _))| (count as u128, Some(i))).collect()];
+    for _ in 1..max_length {
+        let packages: Vec<u128> = {
+            let previous = lists.last().unwrap();
+            previous.chunks(2).filter(|pair| pair.len() == 2).map(|pair| pair[0].0 + pair[1].0)
+                .collect()
+        };
+        let mut list = Vec::with_capacity(leaves.len() + packages.len());
+        let (mut leaf, mut package) = (0, 0);
+        while leaf < leaves.len() || package < packages.len() {
+            if package == packages.len() ||
+               (leaf < leaves.len() && leaves[leaf].0 as u128 <= packages[package]) {
+                list.push((leaves[leaf].0 as u128, Some(leaf)));
+                leaf += 1;
+            } else {
+                list.push((packages[package], None));
+                package += 1;
+            }
+        }
+        lists.push(list);
+    }
+
+    // the first 2n - 2 items of the last list are selected; a selected package selects the
+    // pair it was made of, and each selection of a leaf adds one bit to its code
+    let mut selected = 2 * leaves.len() - 2;
+    for list in lists.iter().rev() {
+        let mut packages = 0;
+        for &(_, item) in &list[..selected] {
+            match item {
+                Some(leaf) => *lengths.get_mut(&leaves[leaf].1).unwrap() += 1,
+                None => packages += 1,
+            }
+        }
+        selected = 2 * packages;
+    }
+    lengths
 }
 
-impl FromBitVec for u8 {
-    fn decode(iter: &mut Iter) -> Result<u8, &'static str> {
-        let mut x = 0u8;
-        for _ in 0..8 {
-            match iter.next() {
-                Some(true) => { x <<= 1; x |= 1; }
-                Some(false) => { x <<= 1; }
-                None => { return Err("unexpected EOF while decoding u8"); }
+/// Writes the code length of every symbol up to the last one having a code. The count and
+/// the bit width of lengths come first, then runs of equal lengths as `(length, run)` pairs
+/// with the run gamma coded, so that unused parts of the alphabet cost a few bits.
+pub fn encode_code_lengths<T: Symbol>(lengths: &BTreeMap<T, u8>) -> BitVec {
+    let mut used = lengths.iter().filter(|&(_, &length)| length > 0);
+    let count = used.clone().last().expect("no code lengths").0.index() + 1;
+    let width = 8 - used.clone().map(|(_, &length)| length).max().unwrap().leading_zeros();
+
+    let mut output = BitVec::new();
+    push_gamma(&mut output, count);
+    push_bits(&mut output, width as u64, 3);
+
+    let mut next = used.next();
+    let mut index = 0;
+    while index < count {
+        let length = match next {
+            Some((symbol, &length)) if symbol.index() == index => {
+                next = used.next();
+                length
+            }
+            _ => 0,
+        };
+        // extend the run over the following symbols with the same length
+        let mut run = 1;
+        if length == 0 {
+            run = next.unwrap().0.index() - index;
+        } else {
+            while let Some((symbol, &next_length)) = next {
+                if symbol.index() != index + run || next_length != length {
+                    break;
+                }
+                run += 1;
+                next = used.next();
             }
         }
-        Ok(x)
+        push_bits(&mut output, length as u64, width);
+        push_gamma(&mut output, run);
+        index += run;
     }
+    output
 }
 
-impl FromBitVec for u64 {
-    fn decode(iter: &mut Iter) -> Result<u64, &'static str> {
-        let mut x = 0u64;
-        for _ in 0..64 {
-            match iter.next() {
-                Some(true) => { x <<= 1; x |= 1; }
-                Some(false) => { x <<= 1; }
-                None => { return Err("unexpected EOF while decoding u64"); }
+impl<T: Symbol> Node<T> {
+    /// Reads code lengths written by `encode_code_lengths` and rebuilds the canonical tree.
+    pub fn decode_code_lengths(iter: &mut BitReader) -> Result<Node<T>, Error> {
+        let start = iter.offset();
+        let count = read_gamma(iter)?;
+        let width = read_bits(iter, 3)? as u32;
+
+        let mut lengths = BTreeMap::new();
+        let mut index = 0;
+        while index < count {
+            let position = iter.offset();
+            let length = read_bits(iter, width)? as u8;
+            let run = read_gamma(iter)?;
+            // every symbol with a code but the partner of a lone symbol occurs at least once
+            // after the header, which also bounds the work done on corrupted runs
+            if run > count - index ||
+               (length > 0 && lengths.len() as u64 + run > iter.remaining() as u64 + 1) {
+                return Err(Error::InvalidTree { offset: Offset::Bit(position) });
+            }
+            if length > 0 {
+                for i in index..index + run {
+                    let symbol = T::from_index(i)
+                        .ok_or(Error::InvalidTree { offset: Offset::Bit(position) })?;
+                    lengths.insert(symbol, length);
+                }
             }
+            index += run;
         }
-        Ok(x)
+
+        Node::from_code_lengths(&lengths)
+            .ok_or(Error::InvalidTree { offset: Offset::Bit(start) })
     }
 }
 
-impl<T: IntoBitVec + FromBitVec + Copy> Node<T> {
-    #[allow(dead_code)]
-    pub fn encode_tree(&self) -> BitVec {
-        match *self {
-            Node::Leaf(ref character) => {
-                let mut output = BitVec::new();
-                output.push(true);
-                append_bit_vec(output, &character.encode())
+
+/// Bits resolved by one table lookup; longer codes go through secondary tables.
+const TABLE_BITS: u32 = 10;
+
+#[derive(Clone, Copy)]
+enum Entry<T> {
+    /// symbol and length of its code in this table
+    Symbol(T, u32),
+    /// index of the table for the next bits
+    Link(usize),
+}
+
+struct Table<T> {
+    bits: u32,
+    entries: Vec<Entry<T>>,
+}
+
+/// Table-driven equivalent of `Node::scan`: peeks up to `TABLE_BITS` bits and usually finds
+/// the symbol in one lookup.
+pub struct Decoder<T> {
+    tables: Vec<Table<T>>,
+}
+
+impl<T: Ord + Copy> Decoder<T> {
+    pub fn new(tree: &Node<T>) -> Decoder<T> {
+        let mut decoder = Decoder { tables: Vec::new() };
+        decoder.add_table(tree);
+        decoder
+    }
+
+    fn add_table(&mut self, root: &Node<T>) -> usize {
+        let bits = cmp::min(TABLE_BITS, root.height());
+        let index = self.tables.len();
+        self.tables.push(Table { bits, entries: Vec::new() });
+        let mut entries = Vec::with_capacity(1 << bits);
+        self.fill(root, 0, bits, &mut entries);
+        self.tables[index].entries = entries;
+        index
+    }
+
+    // visits the subtree left first, so entries are pushed in the order of their bits
+    fn fill(&mut self, node: &Node<T>, depth: u32, bits: u32, entries: &mut Vec<Entry<T>>) {
+        match *node {
+            Node::Leaf(symbol) => {
+                for _ in 0..1 << (bits - depth) {
+                    entries.push(Entry::Symbol(symbol, depth));
+                }
+            }
+            Node::Branch(..) if depth == bits => {
+                let link = self.add_table(node);
+                entries.push(Entry::Link(link));
             }
-            Node::Branch(ref left_node, ref right_node) => {
-                let mut output = BitVec::new();
-                output.push(false);
-                append_bit_vec(append_bit_vec(output, &left_node.encode_tree()),
-                               &right_node.encode_tree())
+            Node::Branch(ref left, ref right) => {
+                self.fill(left, depth + 1, bits, entries);
+                self.fill(right, depth + 1, bits, entries);
             }
         }
     }
 
-    #[allow(dead_code)]
-    pub fn decode_tree(iter: &mut Iter) -> Result<Node<T>, &'static str> {
-        if let Some(val) = iter.next() {
-            if val {
-                Ok(Node::Leaf(T::decode(iter)?))
-            } else {
-                Ok(Node::Branch(Box::new(Self::decode_tree(iter)?),
-                                Box::new(Self::decode_tree(iter)?)))
+    pub fn decode(&self, iter: &mut BitReader) -> Result<Option<T>, Error> {
+        let mut table = &self.tables[0];
+        let mut started = false;
+        loop {
+            let (bits, available) = iter.peek(table.bits);
+            match table.entries[bits as usize] {
+                Entry::Symbol(symbol, length) if length <= available => {
+                    iter.skip(length);
+                    return Ok(Some(symbol));
+                }
+                Entry::Link(next) if available == table.bits => {
+                    iter.skip(table.bits);
+                    table = &self.tables[next];
+                    started = true;
+                }
+                _ if available == 0 && !started => return Ok(None),
+                _ => {
+                    iter.skip(available);
+                    return Err(Error::UnexpectedEof { offset: Offset::Bit(iter.offset()) });
+                }
             }
-        } else {
-            Err("unexpected EOF")
         }
     }
 }
+
+#[test]
+fn canonical_testing() {
+    let mut statistics = BTreeMap::new();
+    for (&symbol, &count) in b"abcde".iter().zip([8u64, 4, 2, 1, 1].iter()) {
+        statistics.insert(symbol, count);
+    }
+    let lengths = code_lengths_from_statistics(&statistics, None);
+    assert_eq!(lengths.values().cloned().collect::<Vec<u8>>(), vec![1, 2, 3, 4, 4]);
+
+    // same lengths as the Huffman tree, codes in order of (length, symbol)
+    let codes = canonical_codes(&lengths);
+    let words: Vec<Vec<bool>> = b"abcde"
+        .iter()
+        .map(|symbol| codes[symbol].iter().collect())
+        .collect();
+    assert_eq!(words,
+               vec![vec![false],
+                    vec![true, false],
+                    vec![true, true, false],
+                    vec![true, true, true, false],
+                    vec![true, true, true, true]]);
+    let tree = Node::from_code_lengths(&lengths).unwrap();
+    assert_eq!(tree.to_dictionnary(BitVec::new()), codes);
+
+    // sparse alphabets only pay for the runs of unused symbols: the serialized tree with
+    // 64 bits per leaf took 131 bits here
+    let mut pointers = BTreeMap::new();
+    pointers.insert(0u64, 1);
+    pointers.insert(1_000_000u64, 1);
+    let mut header = encode_code_lengths(&code_lengths_from_statistics(&pointers, None));
+    assert_eq!(header.len(), 86);
+    header.extend([false, true].iter().cloned());
+    let tree: Node<u64> = Node::decode_code_lengths(&mut BitReader::new(&header)).unwrap();
+    assert_eq!(tree.code_lengths(), code_lengths_from_statistics(&pointers, None));
+
+    let mut incomplete = BTreeMap::new();
+    incomplete.insert(b'a', 1);
+    incomplete.insert(b'b', 2);
+    assert!(Node::from_code_lengths(&incomplete).is_none());
+    incomplete.insert(b'c', 1);
+    assert!(Node::from_code_lengths(&incomplete).is_none());
+}
+
+#[test]
+fn decoder_testing() {
+    // Fibonacci counts give codes of every length up to 23, beyond one table
+    let mut statistics = BTreeMap::new();
+    let (mut a, mut b) = (1u64, 1u64);
+    for symbol in 0..24u8 {
+        statistics.insert(symbol, a);
+        let next = a + b;
+        a = b;
+        b = next;
+    }
+    let lengths = code_lengths_from_statistics(&statistics, None);
+    assert_eq!(lengths.values().cloned().max(), Some(23));
+    let tree = Node::from_code_lengths(&lengths).unwrap();
+    let decoder = Decoder::new(&tree);
+
+    let codes = canonical_codes(&lengths);
+    let mut bits = BitVec::new();
+    for i in 0..500u32 {
+        let symbol = (i * i % 24) as u8;
+        bits.extend(codes[&symbol].iter());
+    }
+    // every truncation, most of them in the middle of a code
+    for len in (0..bits.len() + 1).rev().step_by(7) {
+        let mut truncated = bits.clone();
+        truncated.truncate(len);
+        let (mut by_tree, mut by_table) = (BitReader::new(&truncated), BitReader::new(&truncated));
+        loop {
+            let expected = tree.scan(&mut by_tree);
+            assert_eq!(decoder.decode(&mut by_table), expected);
+            assert_eq!(by_table.offset(), by_tree.offset());
+            match expected {
+                Ok(Some(_)) => {}
+                _ => break,
+            }
+        }
+    }
+}
+
+#[test]
+fn limited_testing() {
+    fn cost(statistics: &BTreeMap<u8, u64>, lengths: &BTreeMap<u8, u8>) -> u64 {
+        statistics.iter().map(|(symbol, &count)| count * lengths[symbol] as u64).sum()
+    }
+
+    let mut statistics = BTreeMap::new();
+    let (mut a, mut b) = (1u64, 1u64);
+    for symbol in 0..24u8 {
+        statistics.insert(symbol, a);
+        let next = a + b;
+        a = b;
+        b = next;
+    }
+    let huffman = Node::from_statistics(&statistics).code_lengths();
+    assert_eq!(limited_code_lengths(&statistics, 23), huffman);
+    assert_eq!(cost(&statistics, &limited_code_lengths(&statistics, 40)),
+               cost(&statistics, &huffman));
+
+    let mut previous = cost(&statistics, &huffman);
+    for max_length in (5..23).rev() {
+        let lengths = limited_code_lengths(&statistics, max_length);
+        assert_eq!(lengths.values().cloned().max(), Some(max_length));
+        assert!(cost(&statistics, &lengths) >= previous);
+        previous = cost(&statistics, &lengths);
+        // still a complete prefix code, usable by the encoders and decoders
+        let tree = Node::from_statistics_limited(&statistics, max_length);
+        assert_eq!(tree.code_lengths(), lengths);
+    }
+
+    let mut statistics = BTreeMap::new();
+    for (&symbol, &count) in b"abcd".iter().zip([1u64, 1, 2, 4].iter()) {
+        statistics.insert(symbol, count);
+    }
+    assert_eq!(limited_code_lengths(&statistics, 2).values().cloned().collect::<Vec<u8>>(),
+               vec![2, 2, 2, 2]);
+    assert_eq!(limited_code_lengths(&statistics, 3).values().cloned().collect::<Vec<u8>>(),
+               vec![3, 3, 2, 1]);
+}
@@ -3,121 +3,693 @@ extern crate bit_vec;
 use bit_vec::BitVec;
 mod huffman;
 use huffman::*;
+pub use huffman::HuffmanParams;
+mod adaptive_huffman;
+use adaptive_huffman::{adaptive_huffman_coding, adaptive_huffman_decoding};
+mod arithmetic;
+use arithmetic::*;
+mod ans;
+use ans::*;
+mod mtf;
+use mtf::{mtf_encode, mtf_decode};
+mod rle;
+use rle::{packbits_encode, packbits_decode, escape_rle_encode, escape_rle_decode,
+          zero_run_encode, zero_run_decode};
+mod filter;
+use filter::{filter_encode, filter_decode};
+pub use filter::{FilterParams, FilterKind};
+mod bwt;
+use bwt::{bwt, inverse_bwt, mtf_runs, inverse_mtf_runs, EOB, MAX_BLOCK_SIZE};
+pub use bwt::BwtParams;
+mod ppm;
+use ppm::{ppm_coding, ppm_decoding};
+pub use ppm::PpmParams;
 mod bitvec_util;
 mod lz_77;
 mod lz_78;
-use lz_77::{lz77_coding, lz77_decoding};
+mod lzw;
+mod container;
+pub use container::Header;
+mod codec;
+pub use codec::{Codec, Chain, Registry, Huffman, AdaptiveHuffman, Lz77, Lzss, Lz78, Lzw,
+                Arithmetic, AdaptiveArithmetic, Rans, Tans, Ppm, Bwt, Mtf, PackBits,
+                EscapeRle, ZeroRuns, Filter};
+mod checksum;
+pub use checksum::Checksum;
+mod error;
+pub use error::{Error, Offset};
+mod stream;
+pub use stream::{Lz77Encoder, Lz77Decoder, BlockEncoder, BlockDecoder};
+mod bytes_util;
+use bytes_util::*;
+use lz_77::{lz77_coding, lz77_decoding, lzss_coding, lzss_decoding, LzssToken, MAX_WINDOW_SIZE,
+            MAX_VIEW_SIZE};
+pub use lz_77::{Lz77Params, LzssParams, Parse};
 use lz_78::{lz78_coding, lz78_decoding};
+pub use lz_78::{Lz78Params, Policy, Entropy};
+use lzw::{lzw_coding, lzw_decoding};
+pub use lzw::LzwParams;
 use bitvec_util::*;
 use std::collections::BTreeMap;
-use std::collections::btree_map::Entry;
 
 pub fn compression_huffman(content: &[u8]) -> Vec<u8> {
-    if content.is_empty() {
+    compression_huffman_with(content, HuffmanParams::default())
+}
+
+/// Huffman coding with codes of at most `max_length` bits, which must be at least 8; the
+/// output is read by `decompression_huffman`.
+pub fn compression_huffman_limited(content: &[u8], max_length: u8) -> Vec<u8> {
+    compression_huffman_with(content, HuffmanParams::new(BLOCK_SIZE, Some(max_length)))
+}
+
+/// Each block is written as its number of bytes (gamma coded), a flag telling whether it
+/// reuses the code of the previous block and, if not, its code lengths, then its codes.
+pub fn compression_huffman_with(content: &[u8], params: HuffmanParams) -> Vec<u8> {
+    assert!(params.is_valid());
+    let mut output = BitVec::new();
+    let mut previous: Option<(BTreeMap<u8, u8>, BTreeMap<u8, BitVec>)> = None;
+
+    for block in content.chunks(params.block_size) {
+        let mut stat = BTreeMap::new();
+        for &byte in block.iter() {
+            *stat.entry(byte).or_insert(0) += 1;
+        }
+        // bits needed by a code, if it has all the bytes of the block
+        let cost = |lengths: &BTreeMap<u8, u8>| -> Option<u64> {
+            stat.iter()
+                .map(|(byte, &count)| {
+                    lengths.get(byte).filter(|&&length| length > 0).map(|&length| {
+                        count * length as u64
+                    })
+                })
+                .sum()
+        };
+
+        let lengths = code_lengths_from_statistics(&stat, params.max_code_length);
+        let header = encode_code_lengths(&lengths);
+        let reuse = match previous {
+            Some((ref previous_lengths, _)) => {
+                cost(previous_lengths).is_some_and(|previous_cost| {
+                    previous_cost <= header.len() as u64 + cost(&lengths).unwrap()
+                })
+            }
+            None => false,
+        };
+
+        push_gamma(&mut output, block.len() as u64);
+        output.push(reuse);
+        if !reuse {
+            output = append_bit_vec(output, &header);
+            let dico = canonical_codes(&lengths);
+            previous = Some((lengths, dico));
+        }
+
+        let dico = &previous.as_ref().unwrap().1;
+        for &byte in block.iter() {
+            output = append_bit_vec(output, &dico[&byte]);
+        }
+    }
+
+    if output.is_empty() {
         return vec![];
     }
-    let mut stat = BTreeMap::new();
+    serialize_bit_vec(&output)
+}
 
-    for &byte in content.iter() {
-        *stat.entry(byte).or_insert(0) += 1;
+pub fn decompression_huffman(content: &[u8]) -> Result<Vec<u8>, Error> {
+    if content.is_empty() {
+        return Ok(vec![]);
     }
-    if stat.len() == 1 {
-        for byte in 0..256 {
-            if let Entry::Vacant(entry) = stat.entry(byte as u8) {
-                entry.insert(1);
-                break;
+    let input = deserialize_bit_vec(content)?;
+    let mut iter = BitReader::new(&input);
+
+    let mut output: Vec<u8> = Vec::new();
+    let mut decoder: Option<Decoder<u8>> = None;
+
+    while iter.remaining() > 0 {
+        let position = iter.offset();
+        let block_len = read_gamma(&mut iter)?;
+        match iter.next() {
+            Some(true) => {}
+            Some(false) => {
+                let tree: Node<u8> = Node::decode_code_lengths(&mut iter)?;
+                decoder = Some(Decoder::new(&tree));
+            }
+            None => return Err(Error::UnexpectedEof { offset: Offset::Bit(iter.offset()) }),
+        }
+        let decoder = match decoder {
+            Some(ref decoder) => decoder,
+            // the first block has no code to reuse
+            None => return Err(Error::InvalidHeader { offset: Offset::Bit(position) }),
+        };
+
+        for _ in 0..block_len {
+            match decoder.decode(&mut iter)? {
+                Some(byte) => output.push(byte),
+                None => return Err(Error::UnexpectedEof { offset: Offset::Bit(iter.offset()) }),
             }
         }
     }
+    Ok(output)
+}
+
+/// Move-to-front, to place before `compression_huffman`: runs of a few distinct bytes become
+/// runs of small indexes.
+pub fn compression_mtf(content: &[u8]) -> Vec<u8> {
+    mtf_encode(content)
+}
 
-    let tree = Node::from_statistics(&stat);
+pub fn decompression_mtf(content: &[u8]) -> Result<Vec<u8>, Error> {
+    Ok(mtf_decode(content))
+}
+
+/// PackBits run-length coding: control bytes below 128 introduce up to 128 literals, the
+/// others a run of up to 128 copies of the next byte.
+pub fn compression_packbits(content: &[u8]) -> Vec<u8> {
+    packbits_encode(content)
+}
+
+pub fn decompression_packbits(content: &[u8]) -> Result<Vec<u8>, Error> {
+    packbits_decode(content)
+}
+
+/// Run-length coding with an escape byte (the least frequent one, written first): runs of 4
+/// or more copies become the escape, the length and the byte; other bytes are kept as is.
+pub fn compression_escape_rle(content: &[u8]) -> Vec<u8> {
+    escape_rle_encode(content)
+}
 
+pub fn decompression_escape_rle(content: &[u8]) -> Result<Vec<u8>, Error> {
+    escape_rle_decode(content)
+}
+
+/// bzip2 zero-run coding: runs of zero bytes become RUNA/RUNB digits (bytes 0 and 1), other
+/// bytes are shifted by one. Best after `compression_mtf` and before `compression_huffman`.
+/// The output starts with the number of bytes as a varint, which bounds the runs.
+pub fn compression_zero_runs(content: &[u8]) -> Vec<u8> {
+    if content.is_empty() {
+        return Vec::new();
+    }
+    let mut output = Vec::new();
+    write_varint(&mut output, content.len() as u64);
+    output.extend(zero_run_encode(content));
+    output
+}
+
+/// Byte offsets in errors count from the end of the header.
+pub fn decompression_zero_runs(content: &[u8]) -> Result<Vec<u8>, Error> {
+    if content.is_empty() {
+        return Ok(Vec::new());
+    }
+    let mut position = 0;
+    let len = read_varint(content, &mut position)?;
+    let output = zero_run_decode(&content[position..], len as usize)?;
+    if output.len() as u64 != len {
+        return Err(Error::InvalidHeader { offset: Offset::Byte(0) });
+    }
+    Ok(output)
+}
+
+pub fn compression_filter(content: &[u8]) -> Vec<u8> {
+    compression_filter_with(content, FilterParams::default())
+}
+
+/// The output starts with a byte holding the stride, and in its high bit whether the filter
+/// is XOR rather than delta; the filtered bytes follow.
+pub fn compression_filter_with(content: &[u8], params: FilterParams) -> Vec<u8> {
+    if content.is_empty() {
+        return Vec::new();
+    }
+    let mut output = vec![params.stride as u8 | ((params.kind == FilterKind::Xor) as u8) << 7];
+    output.extend(filter_encode(content, params));
+    output
+}
+
+pub fn decompression_filter(content: &[u8]) -> Result<Vec<u8>, Error> {
+    if content.is_empty() {
+        return Ok(Vec::new());
+    }
+    let params = FilterParams {
+        kind: if content[0] & 0x80 != 0 { FilterKind::Xor } else { FilterKind::Delta },
+        stride: (content[0] & 0x7f) as usize,
+    };
+    if !params.is_valid() {
+        return Err(Error::InvalidHeader { offset: Offset::Byte(0) });
+    }
+    Ok(filter_decode(&content[1..], params))
+}
+
+pub fn compression_ppm(content: &[u8]) -> Vec<u8> {
+    compression_ppm_with(content, PpmParams::default())
+}
+
+/// Context modeling with arithmetic coding. The output starts with the model order, the
+/// model size cap and the byte count; the code follows as a bit stream.
+pub fn compression_ppm_with(content: &[u8], params: PpmParams) -> Vec<u8> {
+    if content.is_empty() {
+        return Vec::new();
+    }
+    let mut output = vec![params.order as u8];
+    write_varint(&mut output, params.max_nodes as u64);
+    write_varint(&mut output, content.len() as u64);
+    output.extend(serialize_bit_vec(&ppm_coding(content.iter().cloned(), params)));
+    output
+}
+
+/// Bit offsets in errors count from the end of the header.
+pub fn decompression_ppm(content: &[u8]) -> Result<Vec<u8>, Error> {
+    if content.is_empty() {
+        return Ok(Vec::new());
+    }
+    let mut position = 1;
+    let max_nodes = read_varint(content, &mut position)?;
+    let params = PpmParams {
+        order: content[0] as usize,
+        max_nodes: max_nodes.min(usize::MAX as u64) as usize,
+    };
+    if !params.is_valid() {
+        return Err(Error::InvalidHeader { offset: Offset::Byte(0) });
+    }
+    let count = read_varint(content, &mut position)?;
+    let input = deserialize_bit_vec(&content[position..])?;
+    ppm_decoding(&mut BitReader::new(&input), params, count)
+}
+
+pub fn compression_bwt(content: &[u8]) -> Vec<u8> {
+    compression_bwt_with(content, BwtParams::default())
+}
+
+/// Block sorting: the output starts with the block size; then each block is its primary
+/// index, code lengths, and the Huffman codes of its move-to-front and zero-run symbols up to
+/// an end of block symbol.
+pub fn compression_bwt_with(content: &[u8], params: BwtParams) -> Vec<u8> {
+    assert!(params.is_valid(), "invalid BWT parameters {:?}", params);
+    if content.is_empty() {
+        return Vec::new();
+    }
     let mut output = BitVec::new();
-    output = append_bit_vec(output, &tree.encode_tree());
+    for block in content.chunks(params.block_size) {
+        let (last, primary) = bwt(block);
+        let symbols = mtf_runs(&last);
+        let mut statistics = BTreeMap::new();
+        for &symbol in &symbols {
+            *statistics.entry(symbol).or_insert(0) += 1;
+        }
+        let lengths = code_lengths_from_statistics(&statistics, None);
+        let dictionnary = canonical_codes(&lengths);
+
+        push_gamma(&mut output, primary as u64);
+        output = append_bit_vec(output, &encode_code_lengths(&lengths));
+        for symbol in &symbols {
+            output = append_bit_vec(output, &dictionnary[symbol]);
+        }
+    }
 
-    let dico = tree.to_dictionnary(BitVec::new());
+    let mut header = Vec::new();
+    write_varint(&mut header, params.block_size as u64);
+    header.extend(serialize_bit_vec(&output));
+    header
+}
 
-    for &byte in content.iter() {
-        output = append_bit_vec(output, &dico[&byte]);
+/// Bit offsets in errors count from the end of the header.
+pub fn decompression_bwt(content: &[u8]) -> Result<Vec<u8>, Error> {
+    if content.is_empty() {
+        return Ok(Vec::new());
+    }
+    let mut position = 0;
+    let block_size = read_varint(content, &mut position)?;
+    if block_size == 0 || block_size > MAX_BLOCK_SIZE as u64 {
+        return Err(Error::InvalidHeader { offset: Offset::Byte(0) });
     }
+    let input = deserialize_bit_vec(&content[position..])?;
+    let mut iter = BitReader::new(&input);
 
-    serialize_bit_vec(&output)
+    let mut output = Vec::new();
+    while iter.remaining() > 0 {
+        let start = iter.offset();
+        let primary = read_gamma(&mut iter)?;
+        let tree: Node<u16> = Node::decode_code_lengths(&mut iter)?;
+        let decoder = Decoder::new(&tree);
+        let mut symbols = Vec::new();
+        loop {
+            match decoder.decode(&mut iter)? {
+                Some(EOB) => break,
+                Some(symbol) => symbols.push(symbol),
+                None => return Err(Error::UnexpectedEof { offset: Offset::Bit(iter.offset()) }),
+            }
+        }
+        let block = inverse_mtf_runs(&symbols, block_size as usize)
+            .and_then(|last| inverse_bwt(&last, primary as usize))
+            .ok_or(Error::InvalidHeader { offset: Offset::Bit(start) })?;
+        output.extend(block);
+    }
+    Ok(output)
+}
+
+/// Single pass Huffman coding: the code adapts to the symbols seen so far, so the input can
+/// be streamed and no tree is stored.
+pub fn compression_adaptive_huffman<I>(iter: I) -> Vec<u8>
+    where I: Iterator<Item = u8>
+{
+    adaptive_huffman_coding(iter).collect()
 }
 
-pub fn decompression_huffman(content: &[u8]) -> Result<Vec<u8>, &'static str> {
+pub fn decompression_adaptive_huffman(content: &[u8]) -> Result<Vec<u8>, Error> {
     if content.is_empty() {
         return Ok(vec![]);
     }
-    let input = deserialize_bit_vec(content);
-    let mut iter = input.iter();
+    let input = deserialize_bit_vec(content)?;
+    adaptive_huffman_decoding(&mut BitReader::new(&input))
+}
 
-    let tree: Node<u8> = Node::decode_tree(&mut iter)?;
+/// Arithmetic coding with a static order-0 model: the byte count, the model, then the code.
+pub fn compression_arithmetic(content: &[u8]) -> Vec<u8> {
+    if content.is_empty() {
+        return Vec::new();
+    }
+    let mut statistics = BTreeMap::new();
+    for &byte in content {
+        *statistics.entry(byte).or_insert(0) += 1;
+    }
+    let mut model = StaticModel::from_statistics(&statistics);
 
-    let mut output: Vec<u8> = Vec::new();
+    let mut output = BitVec::new();
+    push_gamma(&mut output, content.len() as u64);
+    output = append_bit_vec(output, &model.encode());
+    let mut encoder = ArithmeticEncoder::new();
+    for &byte in content {
+        encoder.encode(&mut model, byte);
+    }
+    serialize_bit_vec(&append_bit_vec(output, &encoder.finish()))
+}
 
-    'b: loop {
-        let byte = match tree.scan(&mut iter) {
-            Ok(Some(byte)) => byte,
-            Ok(None) => {
-                break 'b;
-            }
-            Err(err) => return Err(err),
-        };
-        output.push(byte);
+pub fn decompression_arithmetic(content: &[u8]) -> Result<Vec<u8>, Error> {
+    if content.is_empty() {
+        return Ok(Vec::new());
+    }
+    let input = deserialize_bit_vec(content)?;
+    let mut iter = BitReader::new(&input);
+    let count = read_gamma(&mut iter)?;
+    let mut model: StaticModel<u8> = StaticModel::decode(&mut iter)?;
+    // every symbol takes some of the code, so the count cannot run past it
+    if count > model.max_symbols(iter.remaining()) {
+        return Err(Error::InvalidHeader { offset: Offset::Bit(0) });
+    }
+    let mut decoder = ArithmeticDecoder::new(&mut iter);
+    let mut output = Vec::new();
+    for _ in 0..count {
+        output.push(decoder.decode(&mut model, &mut iter)?);
     }
     Ok(output)
 }
 
+/// Arithmetic coding with an adaptive order-0 model, so the input is read once; symbol 256
+/// marks the end.
+pub fn compression_adaptive_arithmetic<I>(iter: I) -> Vec<u8>
+    where I: Iterator<Item = u8>
+{
+    let mut iter = iter.peekable();
+    if iter.peek().is_none() {
+        return Vec::new();
+    }
+    let mut model: AdaptiveModel<u64> = AdaptiveModel::new(257);
+    let mut encoder = ArithmeticEncoder::new();
+    for byte in iter {
+        encoder.encode(&mut model, byte as u64);
+    }
+    encoder.encode(&mut model, 256);
+    serialize_bit_vec(&encoder.finish())
+}
+
+pub fn decompression_adaptive_arithmetic(content: &[u8]) -> Result<Vec<u8>, Error> {
+    if content.is_empty() {
+        return Ok(Vec::new());
+    }
+    let input = deserialize_bit_vec(content)?;
+    let mut iter = BitReader::new(&input);
+    let mut model: AdaptiveModel<u64> = AdaptiveModel::new(257);
+    let mut decoder = ArithmeticDecoder::new(&mut iter);
+    let mut output = Vec::new();
+    loop {
+        match decoder.decode(&mut model, &mut iter)? {
+            256 => return Ok(output),
+            byte => output.push(byte as u8),
+        }
+    }
+}
+
 pub fn compression_lz77<I>(iter: I) -> Vec<u8>
     where I: Iterator<Item = u8>
 {
-    let lz77_coded: Vec<(u16, u8)> = lz77_coding(iter)
-        .map(|(ptr, len, byte)| {
-            assert!(ptr < 4096, "ptr = {}, len = {}", ptr, len);
-            assert!(len < 16, "ptr = {}, len = {}", ptr, len);
-            (ptr << 4 | len as u16, byte)
-        })
-        .collect();
+    compression_lz77_with(iter, Lz77Params::default())
+}
 
+/// The output starts with the window and view sizes, so `decompression_lz77` needs no
+/// parameters.
+pub fn compression_lz77_with<I>(iter: I, params: Lz77Params) -> Vec<u8>
+    where I: Iterator<Item = u8>
+{
+    let format = Lz77Format::new(params);
     let mut output = Vec::new();
+    format.write_header(&mut output);
+
+    for triplet in lz77_coding(iter, params) {
+        format.pack(triplet, &mut output);
+    }
+
+    output
+}
 
-    for &(ptr_len, byte) in &lz77_coded {
-        let x1 = (ptr_len & 0xff) as u8;
-        let x2 = ((ptr_len >> 8) & 0xff) as u8;
-        output.push(x1);
-        output.push(x2);
+pub fn decompression_lz77(content: &[u8]) -> Result<Vec<u8>, Error> {
+    let mut position = 0;
+    let format = Lz77Format::read_header(content, &mut position)?;
+
+    let size = format.token_size();
+    if !(content.len() - position).is_multiple_of(size) {
+        return Err(Error::UnexpectedEof { offset: Offset::Byte(content.len()) });
+    }
+    let mut lz77_coded: Vec<(usize, usize, u8)> = Vec::new();
+    for bytes in content[position..].chunks(size) {
+        let (ptr, len, byte) = format.unpack(bytes);
+        // the coder never emits a match at distance 0, nor outside of its window
+        if (ptr == 0 && len > 0) || ptr >= format.params.window_size ||
+           len >= format.params.view_size {
+            return Err(Error::InvalidBackReference { offset: Offset::Byte(position) });
+        }
+        lz77_coded.push((ptr, len, byte));
+        position += size;
+    }
+
+    Ok(lz77_decoding(lz77_coded.iter(), format.params).collect())
+}
+
+/// Serialization of LZ77 tokens: a header with the window and view sizes as varints, then
+/// for each `(ptr, len, byte)` the value `ptr << len_bits | len` on as few little endian
+/// bytes as the parameters allow, followed by `byte`.
+struct Lz77Format {
+    params: Lz77Params,
+    len_bits: u32,
+    ptr_len_bytes: usize,
+}
+
+impl Lz77Format {
+    fn new(params: Lz77Params) -> Lz77Format {
+        let (len_bits, ptr_len_bytes) = params.token_layout();
+        Lz77Format {
+            params,
+            len_bits,
+            ptr_len_bytes,
+        }
+    }
+
+    fn token_size(&self) -> usize {
+        self.ptr_len_bytes + 1
+    }
+
+    fn write_header(&self, output: &mut Vec<u8>) {
+        write_varint(output, self.params.window_size as u64);
+        write_varint(output, self.params.view_size as u64);
+    }
+
+    fn read_header(content: &[u8], position: &mut usize) -> Result<Lz77Format, Error> {
+        let start = *position;
+        let window_size = read_varint(content, position)?;
+        let view_size = read_varint(content, position)?;
+        let params = Lz77Params {
+            window_size: window_size as usize,
+            view_size: view_size as usize,
+            max_chain: None,
+            parse: Parse::Greedy,
+        };
+        if window_size > MAX_WINDOW_SIZE as u64 || view_size > MAX_VIEW_SIZE as u64 ||
+           !params.is_valid() {
+            return Err(Error::InvalidHeader { offset: Offset::Byte(start) });
+        }
+        Ok(Lz77Format::new(params))
+    }
+
+    fn pack(&self, (ptr, len, byte): (usize, usize, u8), output: &mut Vec<u8>) {
+        assert!(ptr < self.params.window_size, "ptr = {}, len = {}", ptr, len);
+        assert!(len < self.params.view_size, "ptr = {}, len = {}", ptr, len);
+        let ptr_len = (ptr as u64) << self.len_bits | len as u64;
+        for i in 0..self.ptr_len_bytes {
+            output.push((ptr_len >> (8 * i)) as u8);
+        }
         output.push(byte);
     }
 
+    fn unpack(&self, bytes: &[u8]) -> (usize, usize, u8) {
+        let ptr_len = bytes[..self.ptr_len_bytes]
+            .iter()
+            .rev()
+            .fold(0u64, |ptr_len, &byte| ptr_len << 8 | byte as u64);
+        ((ptr_len >> self.len_bits) as usize,
+         (ptr_len & ((1 << self.len_bits) - 1)) as usize,
+         bytes[self.ptr_len_bytes])
+    }
+}
+
+pub fn compression_lzss(content: &[u8]) -> Vec<u8> {
+    compression_lzss_with(content.iter().cloned(), LzssParams::default())
+}
+
+/// The output starts with the LZSS parameters; then each group of up to 8 tokens is
+/// preceded by a flag byte whose bit `i` is set when token `i` of the group is a match.
+pub fn compression_lzss_with<I>(iter: I, params: LzssParams) -> Vec<u8>
+    where I: Iterator<Item = u8>
+{
+    let format = LzssFormat::new(params);
+    let mut output = Vec::new();
+    format.write_header(&mut output);
+
+    let mut flags_position = 0;
+    for (i, token) in lzss_coding(iter, params).enumerate() {
+        if i % 8 == 0 {
+            flags_position = output.len();
+            output.push(0);
+        }
+        match token {
+            LzssToken::Literal(byte) => output.push(byte),
+            LzssToken::Match { distance, length } => {
+                output[flags_position] |= 1 << (i % 8);
+                format.pack(distance, length, &mut output);
+            }
+        }
+    }
+
     output
 }
 
-pub fn decompression_lz77(content: &[u8]) -> Result<Vec<u8>, &'static str> {
-    let mut iter = content.iter();
+pub fn decompression_lzss(content: &[u8]) -> Result<Vec<u8>, Error> {
+    let mut position = 0;
+    let format = LzssFormat::read_header(content, &mut position)?;
+    let params = format.params;
 
-    let mut lz77_coded: Vec<(u16, u8, u8)> = Vec::new();
+    let mut lzss_coded: Vec<LzssToken> = Vec::new();
+    while position < content.len() {
+        let flags = content[position];
+        position += 1;
+        for i in 0..8 {
+            if position == content.len() {
+                break;
+            }
+            if flags & (1 << i) == 0 {
+                lzss_coded.push(LzssToken::Literal(content[position]));
+                position += 1;
+                continue;
+            }
+            if position + format.match_bytes > content.len() {
+                return Err(Error::UnexpectedEof { offset: Offset::Byte(content.len()) });
+            }
+            let (distance, length) = format.unpack(&content[position..]);
+            if distance == 0 || distance >= params.window_size || length > params.view_size {
+                return Err(Error::InvalidBackReference { offset: Offset::Byte(position) });
+            }
+            lzss_coded.push(LzssToken::Match { distance, length });
+            position += format.match_bytes;
+        }
+    }
+
+    Ok(lzss_decoding(lzss_coded.iter(), params).collect())
+}
+
+/// Serialization of LZSS matches: `distance << length_bits | (length - min_match)` on as
+/// few little endian bytes as the parameters allow.
+struct LzssFormat {
+    params: LzssParams,
+    length_bits: u32,
+    match_bytes: usize,
+}
+
+impl LzssFormat {
+    fn new(params: LzssParams) -> LzssFormat {
+        fn bits(max: usize) -> u32 {
+            usize::BITS - max.leading_zeros()
+        }
+        let length_bits = bits(params.view_size - params.min_match);
+        let distance_bits = bits(params.window_size - 1);
+        LzssFormat {
+            params,
+            length_bits,
+            match_bytes: (distance_bits + length_bits).div_ceil(8) as usize,
+        }
+    }
+
+    fn write_header(&self, output: &mut Vec<u8>) {
+        write_varint(output, self.params.window_size as u64);
+        write_varint(output, self.params.view_size as u64);
+        write_varint(output, self.params.min_match as u64);
+    }
+
+    fn read_header(content: &[u8], position: &mut usize) -> Result<LzssFormat, Error> {
+        let start = *position;
+        let window_size = read_varint(content, position)?;
+        let view_size = read_varint(content, position)?;
+        let min_match = read_varint(content, position)?;
+        if window_size > MAX_WINDOW_SIZE as u64 || view_size > MAX_VIEW_SIZE as u64 ||
+           min_match > view_size {
+            return Err(Error::InvalidHeader { offset: Offset::Byte(start) });
+        }
+        let params = LzssParams {
+            window_size: window_size as usize,
+            view_size: view_size as usize,
+            min_match: min_match as usize,
+            max_chain: None,
+        };
+        if !params.is_valid() {
+            return Err(Error::InvalidHeader { offset: Offset::Byte(start) });
+        }
+        Ok(LzssFormat::new(params))
+    }
 
-    while let Some(&x1) = iter.next() {
-        // loop {
-        //     let x1 = match iter.next() {
-        //         Some(&x1) => x1,
-        //         None => break
-        //     };
-        let x2 = *iter.next().ok_or("Unexpected EOF")?;
-        let byte = *iter.next().ok_or("Unexpected EOF")?;
-        let ptr_len = ((x2 as u16) << 8) | x1 as u16;
-        let triplet = (ptr_len >> 4, (ptr_len & 0b1111) as u8, byte);
-        lz77_coded.push(triplet);
+    fn pack(&self, distance: usize, length: usize, output: &mut Vec<u8>) {
+        let value = (distance as u64) << self.length_bits | (length - self.params.min_match) as u64;
+        for i in 0..self.match_bytes {
+            output.push((value >> (8 * i)) as u8);
+        }
     }
 
-    Ok(lz77_decoding(lz77_coded.iter()).collect())
+    fn unpack(&self, bytes: &[u8]) -> (usize, usize) {
+        let value = bytes[..self.match_bytes]
+            .iter()
+            .rev()
+            .fold(0u64, |value, &byte| value << 8 | byte as u64);
+        ((value >> self.length_bits) as usize,
+         (value & ((1 << self.length_bits) - 1)) as usize + self.params.min_match)
+    }
 }
 
 pub fn compression_lz78(content: &[u8]) -> Vec<u8> {
+    compression_lz78_with(content, Lz78Params::default())
+}
+
+/// The output starts with the dictionary size as a varint and a byte for the policy; then
+/// come the code lengths and the codes of the pairs as a bit stream.
+pub fn compression_lz78_with(content: &[u8], params: Lz78Params) -> Vec<u8> {
     if content.is_empty() {
         return Vec::new();
     }
-    let lz78_coded: Vec<(u64, u8)> = lz78_coding(content.iter());
+    let lz78_coded: Vec<(u64, u8)> = lz78_coding(content.iter(), params);
 
     let (mut pointer_statistic, mut character_statistic): (BTreeMap<u64, u64>,
                                                            BTreeMap<u8, u64>) = (BTreeMap::new(),
@@ -128,66 +700,392 @@ pub fn compression_lz78(content: &[u8]) -> Vec<u8> {
         *character_statistic.entry(character).or_insert(0) += 1;
     }
 
-    let (pointer_tree, character_tree) = (Node::from_statistics(&pointer_statistic),
-                                          Node::from_statistics(&character_statistic));
+    let output = match params.entropy {
+        Entropy::Huffman => {
+            let (pointer_lengths, character_lengths) =
+                (code_lengths_from_statistics(&pointer_statistic, None),
+                 code_lengths_from_statistics(&character_statistic, None));
 
-    let mut output = BitVec::new();
-    let pointer_code = pointer_tree.encode_tree();
-    let character_code = character_tree.encode_tree();
-    println!("Pointer tree size {} ({} pointers)",
-             pointer_code.len(),
-             pointer_statistic.len());
-    println!("Character tree size {} ({} characters)",
-             character_code.len(),
-             character_statistic.len());
-    output = append_bit_vec(output, &pointer_code);
-    output = append_bit_vec(output, &character_code);
-
-    let (pointer_dictionnary, character_dictionnary) =
-        (pointer_tree.to_dictionnary(BitVec::new()), character_tree.to_dictionnary(BitVec::new()));
-
-    //println!("Pointer dictionnary : {:?}", pointer_dictionnary);
-    //println!("Character dictionnary : {:?}", character_dictionnary);
+            let mut output = encode_code_lengths(&pointer_lengths);
+            output = append_bit_vec(output, &encode_code_lengths(&character_lengths));
 
-    for &(pointer, character) in &lz78_coded {
-        output = append_bit_vec(output, &pointer_dictionnary[&pointer]);
-        output = append_bit_vec(output, &character_dictionnary[&character]);
-    }
-    println!("Total size {}", output.len());
+            let (pointer_dictionnary, character_dictionnary) =
+                (canonical_codes(&pointer_lengths), canonical_codes(&character_lengths));
 
-    serialize_bit_vec(&output)
+            for &(pointer, character) in &lz78_coded {
+                output = append_bit_vec(output, &pointer_dictionnary[&pointer]);
+                output = append_bit_vec(output, &character_dictionnary[&character]);
+            }
+            output
+        }
+        // the other coders need the pair count
+        Entropy::Arithmetic => {
+            let (mut pointer_model, mut character_model) =
+                (StaticModel::from_statistics(&pointer_statistic),
+                 StaticModel::from_statistics(&character_statistic));
+            let mut output = BitVec::new();
+            push_gamma(&mut output, lz78_coded.len() as u64);
+            output = append_bit_vec(output, &pointer_model.encode());
+            output = append_bit_vec(output, &character_model.encode());
+            let mut encoder = ArithmeticEncoder::new();
+            for &(pointer, character) in &lz78_coded {
+                encoder.encode(&mut pointer_model, pointer);
+                encoder.encode(&mut character_model, character);
+            }
+            append_bit_vec(output, &encoder.finish())
+        }
+        Entropy::Rans | Entropy::Tans => {
+            // one table size for both, so that tANS can share its state between them
+            let log = table_log(pointer_statistic.len().max(character_statistic.len()));
+            let (pointer_frequencies, character_frequencies) =
+                (Frequencies::from_statistics(&pointer_statistic, log),
+                 Frequencies::from_statistics(&character_statistic, log));
+            let mut output = BitVec::new();
+            push_gamma(&mut output, lz78_coded.len() as u64);
+            output = append_bit_vec(output, &pointer_frequencies.encode());
+            output = append_bit_vec(output, &character_frequencies.encode());
+            let code = if params.entropy == Entropy::Rans {
+                let mut encoder = RansEncoder::new();
+                for &(pointer, character) in lz78_coded.iter().rev() {
+                    encoder.put(&character_frequencies, character);
+                    encoder.put(&pointer_frequencies, pointer);
+                }
+                encoder.finish()
+            } else {
+                let (pointer_table, character_table) =
+                    (TansTable::new(pointer_frequencies), TansTable::new(character_frequencies));
+                let mut encoder = TansEncoder::new(log);
+                for &(pointer, character) in lz78_coded.iter().rev() {
+                    encoder.put(&character_table, character);
+                    encoder.put(&pointer_table, pointer);
+                }
+                encoder.finish()
+            };
+            append_bit_vec(output, &code)
+        }
+    };
+
+    let mut header = Vec::new();
+    write_varint(&mut header, params.max_entries as u64);
+    header.push(match params.policy {
+        Policy::Freeze => 0,
+        Policy::Reset => 1,
+        Policy::Lru => 2,
+    });
+    header.push(match params.entropy {
+        Entropy::Huffman => 0,
+        Entropy::Arithmetic => 1,
+        Entropy::Rans => 2,
+        Entropy::Tans => 3,
+    });
+    header.extend(serialize_bit_vec(&output));
+    header
 }
 
-pub fn decompression_lz78(content: &[u8]) -> Result<Vec<u8>, &'static str> {
+/// Bit offsets in errors count from the end of the header.
+pub fn decompression_lz78(content: &[u8]) -> Result<Vec<u8>, Error> {
     if content.is_empty() {
         return Ok(Vec::new());
     }
-    let input = deserialize_bit_vec(content);
-    let mut iter = input.iter();
-
-    let (pointer_tree, character_tree) = (Node::decode_tree(&mut iter)?,
-                                          Node::decode_tree(&mut iter)?);
+    let mut position = 0;
+    let max_entries = read_varint(content, &mut position)?;
+    let policy = match content.get(position) {
+        Some(0) => Policy::Freeze,
+        Some(1) => Policy::Reset,
+        Some(2) => Policy::Lru,
+        Some(_) => return Err(Error::InvalidHeader { offset: Offset::Byte(position) }),
+        None => return Err(Error::UnexpectedEof { offset: Offset::Byte(position) }),
+    };
+    let entropy = match content.get(position + 1) {
+        Some(0) => Entropy::Huffman,
+        Some(1) => Entropy::Arithmetic,
+        Some(2) => Entropy::Rans,
+        Some(3) => Entropy::Tans,
+        Some(_) => return Err(Error::InvalidHeader { offset: Offset::Byte(position + 1) }),
+        None => return Err(Error::UnexpectedEof { offset: Offset::Byte(position + 1) }),
+    };
+    let params = Lz78Params {
+        max_entries: max_entries.min(usize::MAX as u64) as usize,
+        policy,
+        entropy,
+    };
+    if !params.is_valid() {
+        return Err(Error::InvalidHeader { offset: Offset::Byte(0) });
+    }
+    let input = deserialize_bit_vec(&content[position + 2..])?;
+    let mut iter = BitReader::new(&input);
 
     let mut lz78_coded: Vec<(u64, u8)> = Vec::new();
+    // pair number n can only refer to one of the n entries defined before it
+    let check = |pointer: u64, count: usize, position: usize| {
+        if pointer > count as u64 || pointer > max_entries {
+            return Err(Error::InvalidBackReference { offset: Offset::Bit(position) });
+        }
+        Ok(())
+    };
 
-    if let (&Node::Leaf(pointer), &Node::Leaf(character)) = (&pointer_tree, &character_tree) {
-        lz78_coded.push((pointer, character));
-    } else {
+    match entropy {
+        Entropy::Huffman => {
+            let pointer_tree: Node<u64> = Node::decode_code_lengths(&mut iter)?;
+            let character_tree: Node<u8> = Node::decode_code_lengths(&mut iter)?;
+            let (pointer_decoder, character_decoder) =
+                (Decoder::new(&pointer_tree), Decoder::new(&character_tree));
 
-        while let Some(pointer_code) = pointer_tree.scan(&mut iter)? {
-            if let Some(character_code) = character_tree.scan(&mut iter)? {
-                lz78_coded.push((pointer_code, character_code));
-            } else if let Node::Leaf(_) = pointer_tree {
-                break;
+            loop {
+                let position = iter.offset();
+                let pointer_code = match pointer_decoder.decode(&mut iter)? {
+                    Some(pointer_code) => pointer_code,
+                    None => break,
+                };
+                match character_decoder.decode(&mut iter)? {
+                    Some(character_code) => {
+                        check(pointer_code, lz78_coded.len(), position)?;
+                        lz78_coded.push((pointer_code, character_code));
+                    }
+                    None => {
+                        return Err(Error::UnexpectedEof { offset: Offset::Bit(iter.offset()) })
+                    }
+                }
+            }
+        }
+        Entropy::Arithmetic => {
+            let count_position = iter.offset();
+            let count = read_gamma(&mut iter)?;
+            let mut pointer_model: StaticModel<u64> = StaticModel::decode(&mut iter)?;
+            let mut character_model: StaticModel<u8> = StaticModel::decode(&mut iter)?;
+            let bits = iter.remaining();
+            if count > pointer_model.max_symbols(bits).min(character_model.max_symbols(bits)) {
+                return Err(Error::InvalidHeader { offset: Offset::Bit(count_position) });
+            }
+            let mut decoder = ArithmeticDecoder::new(&mut iter);
+            for _ in 0..count {
+                let position = iter.offset();
+                let pointer = decoder.decode(&mut pointer_model, &mut iter)?;
+                check(pointer, lz78_coded.len(), position)?;
+                lz78_coded.push((pointer, decoder.decode(&mut character_model, &mut iter)?));
+            }
+        }
+        Entropy::Rans | Entropy::Tans => {
+            let count_position = iter.offset();
+            let count = read_gamma(&mut iter)?;
+            let table_position = iter.offset();
+            let pointer_frequencies: Frequencies<u64> = Frequencies::decode(&mut iter)?;
+            let character_frequencies: Frequencies<u8> = Frequencies::decode(&mut iter)?;
+            let log = pointer_frequencies.table_log;
+            if character_frequencies.table_log != log {
+                return Err(Error::InvalidHeader { offset: Offset::Bit(table_position) });
+            }
+            let bits = iter.remaining();
+            if count > pointer_frequencies.max_symbols(bits)
+                .min(character_frequencies.max_symbols(bits)) {
+                return Err(Error::InvalidHeader { offset: Offset::Bit(count_position) });
+            }
+            if entropy == Entropy::Rans {
+                let (pointer_table, character_table) =
+                    (RansTable::new(pointer_frequencies), RansTable::new(character_frequencies));
+                let mut decoder = RansDecoder::new(&mut iter)?;
+                for _ in 0..count {
+                    let position = iter.offset();
+                    let pointer = decoder.get(&pointer_table, &mut iter)?;
+                    check(pointer, lz78_coded.len(), position)?;
+                    lz78_coded.push((pointer, decoder.get(&character_table, &mut iter)?));
+                }
             } else {
-                return Err("No character for given pointer");
+                let (pointer_table, character_table) =
+                    (TansTable::new(pointer_frequencies), TansTable::new(character_frequencies));
+                let mut decoder = TansDecoder::new(&mut iter, log)?;
+                for _ in 0..count {
+                    let position = iter.offset();
+                    let pointer = decoder.get(&pointer_table, &mut iter)?;
+                    check(pointer, lz78_coded.len(), position)?;
+                    lz78_coded.push((pointer, decoder.get(&character_table, &mut iter)?));
+                }
             }
         }
     }
 
-    lz78_decoding(lz78_coded.iter())
+    lz78_decoding(lz78_coded.iter(), params)
+}
+
+/// rANS over bytes: the byte count, the normalized frequencies, then the code.
+pub fn compression_rans(content: &[u8]) -> Vec<u8> {
+    if content.is_empty() {
+        return Vec::new();
+    }
+    let frequencies = byte_frequencies(content);
+    let mut output = BitVec::new();
+    push_gamma(&mut output, content.len() as u64);
+    output = append_bit_vec(output, &frequencies.encode());
+    let mut encoder = RansEncoder::new();
+    for &byte in content.iter().rev() {
+        encoder.put(&frequencies, byte);
+    }
+    serialize_bit_vec(&append_bit_vec(output, &encoder.finish()))
+}
+
+pub fn decompression_rans(content: &[u8]) -> Result<Vec<u8>, Error> {
+    if content.is_empty() {
+        return Ok(Vec::new());
+    }
+    let input = deserialize_bit_vec(content)?;
+    let mut iter = BitReader::new(&input);
+    let count = read_gamma(&mut iter)?;
+    let frequencies = Frequencies::decode(&mut iter)?;
+    // every symbol takes some of the code, so the count cannot run past it
+    if count > frequencies.max_symbols(iter.remaining()) {
+        return Err(Error::InvalidHeader { offset: Offset::Bit(0) });
+    }
+    let table = RansTable::new(frequencies);
+    let mut decoder = RansDecoder::new(&mut iter)?;
+    let mut output = Vec::new();
+    for _ in 0..count {
+        output.push(decoder.get(&table, &mut iter)?);
+    }
+    Ok(output)
+}
+
+/// tANS over bytes, laid out as `compression_rans`.
+pub fn compression_tans(content: &[u8]) -> Vec<u8> {
+    if content.is_empty() {
+        return Vec::new();
+    }
+    let frequencies = byte_frequencies(content);
+    let mut output = BitVec::new();
+    push_gamma(&mut output, content.len() as u64);
+    output = append_bit_vec(output, &frequencies.encode());
+    let log = frequencies.table_log;
+    let table = TansTable::new(frequencies);
+    let mut encoder = TansEncoder::new(log);
+    for &byte in content.iter().rev() {
+        encoder.put(&table, byte);
+    }
+    serialize_bit_vec(&append_bit_vec(output, &encoder.finish()))
 }
 
+pub fn decompression_tans(content: &[u8]) -> Result<Vec<u8>, Error> {
+    if content.is_empty() {
+        return Ok(Vec::new());
+    }
+    let input = deserialize_bit_vec(content)?;
+    let mut iter = BitReader::new(&input);
+    let count = read_gamma(&mut iter)?;
+    let frequencies = Frequencies::decode(&mut iter)?;
+    if count > frequencies.max_symbols(iter.remaining()) {
+        return Err(Error::InvalidHeader { offset: Offset::Bit(0) });
+    }
+    let log = frequencies.table_log;
+    let table = TansTable::new(frequencies);
+    let mut decoder = TansDecoder::new(&mut iter, log)?;
+    let mut output = Vec::new();
+    for _ in 0..count {
+        output.push(decoder.get(&table, &mut iter)?);
+    }
+    Ok(output)
+}
+
+fn byte_frequencies(content: &[u8]) -> Frequencies<u8> {
+    let mut statistics = BTreeMap::new();
+    for &byte in content {
+        *statistics.entry(byte).or_insert(0) += 1;
+    }
+    Frequencies::from_statistics(&statistics, table_log(statistics.len()))
+}
+
+pub fn compression_lzw(content: &[u8]) -> Vec<u8> {
+    compression_lzw_with(content.iter().cloned(), LzwParams::default())
+}
+
+/// The output starts with a byte holding the maximum code width, and the clear flag in its
+/// high bit; the codes follow as a bit stream.
+pub fn compression_lzw_with<I>(iter: I, params: LzwParams) -> Vec<u8>
+    where I: Iterator<Item = u8>
+{
+    let coded = lzw_coding(iter, params);
+    if coded.is_empty() {
+        return Vec::new();
+    }
+    let mut output = vec![params.max_bits as u8 | (params.clear as u8) << 7];
+    output.extend(serialize_bit_vec(&coded));
+    output
+}
+
+/// Bit offsets in errors count from the byte after the header.
+pub fn decompression_lzw(content: &[u8]) -> Result<Vec<u8>, Error> {
+    if content.is_empty() {
+        return Ok(Vec::new());
+    }
+    let params = LzwParams {
+        max_bits: (content[0] & 0x7f) as u32,
+        clear: content[0] & 0x80 != 0,
+    };
+    if !params.is_valid() {
+        return Err(Error::InvalidHeader { offset: Offset::Byte(0) });
+    }
+    let input = deserialize_bit_vec(&content[1..])?;
+    lzw_decoding(&mut BitReader::new(&input), params)
+}
+
+pub fn compress(content: &[u8], chain: &Chain) -> Vec<u8> {
+    compress_with_checksum(content, chain, Checksum::default())
+}
+
+pub fn compress_with_checksum(content: &[u8], chain: &Chain, checksum: Checksum) -> Vec<u8> {
+    let mut output = Vec::new();
+    Header::new(&chain.ids(), content.len() as u64, checksum).write(&mut output);
+
+    output.extend_from_slice(&chain.encode(content));
+    let sum = checksum.compute(content);
+    for i in 0..checksum.size() {
+        output.push((sum >> (8 * i)) as u8);
+    }
+    output
+}
+
+pub fn decompress(content: &[u8]) -> Result<Vec<u8>, Error> {
+    decompress_with(&Registry::default(), content)
+}
+
+/// Decompresses a container whose stages may include codecs registered in `registry`.
+pub fn decompress_with(registry: &Registry, content: &[u8]) -> Result<Vec<u8>, Error> {
+    let (header, payload) = Header::read(content)?;
+    let chain = registry.chain_from_ids(&header.pipeline)
+        .map_err(|index| {
+            Error::UnknownAlgorithm {
+                id: header.pipeline[index],
+                offset: Header::stage_offset(index),
+            }
+        })?;
+
+    let size = header.checksum.size();
+    if payload.len() < size {
+        return Err(Error::UnexpectedEof { offset: Offset::Byte(content.len()) });
+    }
+    let (payload, trailer) = payload.split_at(payload.len() - size);
+
+    let data = chain.decode(payload)?;
+
+    if header.checksum != Checksum::Disabled {
+        let expected = trailer.iter().rev().fold(0u32, |sum, &byte| sum << 8 | byte as u32);
+        let found = header.checksum.compute(&data);
+        if expected != found {
+            return Err(Error::ChecksumMismatch {
+                expected,
+                found,
+                offset: Offset::Byte(content.len() - size),
+            });
+        }
+    }
+
+    // after the checksum, so that corruption changing the length is reported as such
+    if data.len() as u64 != header.original_len {
+        return Err(Error::LengthMismatch {
+            expected: header.original_len,
+            found: data.len() as u64,
+            offset: header.length_offset(),
+        });
+    }
+    Ok(data)
+}
 
 #[test]
 fn identity_test() {
@@ -243,3 +1141,496 @@ fn identity5_test() {
     println!("Decoded {:?}", decoded);
     assert_eq!(input, decoded)
 }
+
+#[test]
+fn container_test() {
+    let input = "abracadabra abracadabra abracadabra".as_bytes().to_vec();
+    let registry = Registry::default();
+    for names in &["store", "huffman", "lz77", "lz78", "lz77+huffman"] {
+        let chain = registry.chain(names).unwrap();
+        let coded = compress(&input, &chain);
+        let (header, _) = Header::read(&coded).unwrap();
+        assert_eq!(header.pipeline, chain.ids());
+        assert_eq!(header.original_len, input.len() as u64);
+        assert_eq!(input, decompress(&coded).unwrap());
+    }
+
+    assert_eq!(decompress(&compression_huffman(&input)), Err(Error::InvalidMagic));
+
+    let mut coded = compress(&input, &registry.chain("lz77+huffman").unwrap());
+    coded[7] = 200;
+    assert_eq!(decompress(&coded),
+               Err(Error::UnknownAlgorithm {
+                   id: 200,
+                   offset: Offset::Byte(7),
+               }));
+}
+
+#[test]
+fn checksum_test() {
+    let input = "abracadabra abracadabra abracadabra".as_bytes().to_vec();
+    let lz78 = Registry::default().chain("lz78").unwrap();
+    for &checksum in &[Checksum::Disabled, Checksum::Crc32, Checksum::Adler32] {
+        let coded = compress_with_checksum(&input, &lz78, checksum);
+        assert_eq!(input, decompress(&coded).unwrap());
+    }
+
+    // a flipped bit in the raw payload still decodes, but not to the original data
+    let mut coded = compress(&input, &Chain::new());
+    let last = coded.len() - 5;
+    coded[last] ^= 0x01;
+    match decompress(&coded) {
+        Err(Error::ChecksumMismatch { offset, .. }) => {
+            assert_eq!(offset, Offset::Byte(coded.len() - 4))
+        }
+        other => panic!("expected a checksum mismatch, got {:?}", other),
+    }
+
+    // corruption that changes the decoded length (here, of the last LZ77 match) is reported
+    // by the checksum, not as a length mismatch
+    let lz77 = Registry::default().chain("lz77").unwrap();
+    for &checksum in &[Checksum::Disabled, Checksum::Crc32] {
+        let mut coded = compress_with_checksum(&input, &lz77, checksum);
+        let length = coded.len() - checksum.size() - 3;
+        coded[length] ^= 0x02;
+        match (checksum, decompress(&coded)) {
+            (Checksum::Disabled, Err(Error::LengthMismatch { found: 37, .. })) => {}
+            (Checksum::Crc32, Err(Error::ChecksumMismatch { .. })) => {}
+            (_, other) => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    // without a checksum, only the length is left to check
+    let coded = compress_with_checksum(&input, &Chain::new(), Checksum::Disabled);
+    match decompress(&coded[..coded.len() - 10]) {
+        Err(Error::LengthMismatch { offset, .. }) => assert_eq!(offset, Offset::Byte(6)),
+        other => panic!("expected a length mismatch, got {:?}", other),
+    }
+}
+
+#[test]
+fn error_test() {
+    let input = "abracadabra abracadabra abracadabra".as_bytes().to_vec();
+
+    let coded = compression_huffman(&input);
+    // cut in the middle of the block length
+    assert_eq!(decompression_huffman(&[coded[0], 0]),
+               Err(Error::UnexpectedEof { offset: Offset::Bit(8) }));
+    assert_eq!(decompression_huffman(&coded[..coded.len() - 1]),
+               Err(Error::UnexpectedEof { offset: Offset::Byte(coded.len() - 2) }));
+    // one byte, new code: count 1, lengths on 1 bit, one symbol of length 1, which is not a
+    // complete code
+    assert_eq!(decompression_huffman(&[0b1010_0111, 0]),
+               Err(Error::InvalidTree { offset: Offset::Bit(2) }));
+    // count 2, two symbols of length 2: a code with unused words
+    assert_eq!(decompression_huffman(&[0b1001_0010, 0b1001_0000, 0, 0]),
+               Err(Error::InvalidTree { offset: Offset::Bit(2) }));
+    // the first block cannot reuse a code
+    assert_eq!(decompression_huffman(&[0b1100_0000, 6]),
+               Err(Error::InvalidHeader { offset: Offset::Bit(0) }));
+    // a gamma code longer than 64 bits
+    assert_eq!(decompression_huffman(&[0; 64]),
+               Err(Error::InvalidHeader { offset: Offset::Bit(0) }));
+
+    let coded = compression_lz77(input.iter().cloned());
+    assert_eq!(decompression_lz77(&coded[..coded.len() - 1]),
+               Err(Error::UnexpectedEof { offset: Offset::Byte(coded.len() - 1) }));
+    // header: window 4096, view 16
+    assert_eq!(decompression_lz77(&[0x80, 0x20, 16, 0, 0, 1, 0x01, 0x00, 2]),
+               Err(Error::InvalidBackReference { offset: Offset::Byte(6) }));
+    assert_eq!(decompression_lz77(&[16, 16]),
+               Err(Error::InvalidHeader { offset: Offset::Byte(0) }));
+    // the largest window is only paid for as it fills up
+    let header = [0x80, 0x80, 0x80, 0x80, 0x04, 16];
+    assert_eq!(decompression_lz77(&[&header[..], &[0, 0, 0, 0, 0, 7]].concat()), Ok(vec![7]));
+}
+
+#[test]
+fn lz77_params_test() {
+    let mut input = Vec::new();
+    for i in 0..2000 {
+        let line = format!("line {} of a rather redundant log file\n", i % 50);
+        input.extend_from_slice(line.as_bytes());
+    }
+
+    let default = compression_lz77(input.iter().cloned());
+    // 2 bytes per ptr/len pair, as before parameters were configurable
+    assert_eq!((default.len() - 3) % 3, 0);
+    assert_eq!(input, decompression_lz77(&default).unwrap());
+
+    let large = compression_lz77_with(input.iter().cloned(), Lz77Params::new(1 << 20, 512));
+    assert!(large.len() < default.len());
+    assert_eq!(input, decompression_lz77(&large).unwrap());
+
+    let codec = Lz77 { params: Lz77Params::new(1 << 20, 512) };
+    assert_eq!(input, codec.decode(&codec.encode(&input)).unwrap());
+
+    // a shorter search finds shorter matches, in the same format
+    let limited = Lz77Params { max_chain: Some(1), ..Lz77Params::default() };
+    let fast = compression_lz77_with(input.iter().cloned(), limited);
+    assert!(fast.len() >= default.len());
+    assert_eq!(input, decompression_lz77(&fast).unwrap());
+
+    // same format; the tokens of the optimal parse are cheaper for an entropy coder
+    for &parse in &[Parse::Lazy, Parse::Optimal] {
+        let params = Lz77Params { parse, ..Lz77Params::default() };
+        let coded = compression_lz77_with(input.iter().cloned(), params);
+        assert_eq!(input, decompression_lz77(&coded).unwrap());
+        if parse == Parse::Optimal {
+            assert!(compression_huffman(&coded).len() < compression_huffman(&default).len());
+        }
+    }
+}
+
+#[test]
+fn lzss_test() {
+    let mut input = Vec::new();
+    for i in 0..500 {
+        let line = format!("line {} of a rather redundant log file\n", i % 50);
+        input.extend_from_slice(line.as_bytes());
+    }
+    input.extend(0..255);
+
+    let lz77 = compression_lz77(input.iter().cloned());
+    let lzss = compression_lzss(&input);
+    assert!(lzss.len() < lz77.len());
+    assert_eq!(input, decompression_lzss(&lzss).unwrap());
+
+    for &params in &[LzssParams::new(16, 4, 1), LzssParams::new(1 << 16, 258, 4)] {
+        let coded = compression_lzss_with(input.iter().cloned(), params);
+        assert_eq!(input, decompression_lzss(&coded).unwrap());
+    }
+
+    assert_eq!(decompression_lzss(&compression_lzss(&[])).unwrap(), vec![]);
+    assert_eq!(decompression_lzss(&[0x80]),
+               Err(Error::UnexpectedEof { offset: Offset::Byte(1) }));
+    // header: window 4096, view 18, min match 3, then a match at distance 0
+    assert_eq!(decompression_lzss(&[0x80, 0x20, 18, 3, 0x01, 0x00, 0x00]),
+               Err(Error::InvalidBackReference { offset: Offset::Byte(5) }));
+
+    let chain = Registry::default().chain("lzss+huffman").unwrap();
+    let coded = chain.encode(&input);
+    assert!(coded.len() < lzss.len());
+    assert_eq!(input, chain.decode(&coded).unwrap());
+}
+
+#[test]
+fn huffman_limited_test() {
+    let mut input = Vec::new();
+    for i in 0..20 {
+        input.extend(vec![i as u8; 1 << i]);
+    }
+    let unlimited = compression_huffman(&input);
+    let limited = compression_huffman_limited(&input, 12);
+    assert!(limited.len() > unlimited.len());
+    assert_eq!(input, decompression_huffman(&limited).unwrap());
+    assert_eq!(input, decompression_huffman(&unlimited).unwrap());
+}
+
+#[test]
+fn huffman_blocks_test() {
+    // text, then bytes with a different distribution, then text again
+    let mut input = Vec::new();
+    for i in 0..300 {
+        input.extend_from_slice(format!("line {} of a rather redundant log file\n", i).as_bytes());
+    }
+    for i in 0..12000u32 {
+        input.push((i * i % 256) as u8);
+    }
+    for i in 0..300 {
+        input.extend_from_slice(format!("line {} of a rather redundant log file\n", i).as_bytes());
+    }
+
+    let whole = compression_huffman_with(&input, HuffmanParams::new(input.len(), None));
+    let blocks = compression_huffman_with(&input, HuffmanParams::new(4096, None));
+    assert!(blocks.len() < whole.len());
+    assert_eq!(input, decompression_huffman(&whole).unwrap());
+    assert_eq!(input, decompression_huffman(&blocks).unwrap());
+
+    // blocks with the same statistics reuse the first code: one bit and a length each
+    let input: Vec<u8> = "abcdefgh".bytes().cycle().take(8000).collect();
+    let blocks = compression_huffman_with(&input, HuffmanParams::new(800, None));
+    let whole = compression_huffman_with(&input, HuffmanParams::new(8000, None));
+    assert!(blocks.len() <= whole.len() + 9 * 21 / 8 + 1);
+    assert_eq!(input, decompression_huffman(&blocks).unwrap());
+
+    let codec = Huffman { params: HuffmanParams::new(100, Some(9)) };
+    assert_eq!(input, codec.decode(&codec.encode(&input)).unwrap());
+}
+
+#[test]
+fn adaptive_huffman_test() {
+    let mut input = Vec::new();
+    for i in 0..500 {
+        let line = format!("line {} of a rather redundant log file\n", i % 50);
+        input.extend_from_slice(line.as_bytes());
+    }
+    let adaptive = compression_adaptive_huffman(input.iter().cloned());
+    assert!(adaptive.len() < input.len() / 2);
+    assert_eq!(input, decompression_adaptive_huffman(&adaptive).unwrap());
+
+    // the LZ77 tokens can be coded while they are produced
+    let tokens = compression_lz77(input.iter().cloned());
+    let coded = compression_adaptive_huffman(tokens.iter().cloned());
+    assert_eq!(tokens, decompression_adaptive_huffman(&coded).unwrap());
+
+    assert_eq!(decompression_adaptive_huffman(&adaptive[..adaptive.len() - 1]),
+               Err(Error::UnexpectedEof { offset: Offset::Byte(adaptive.len() - 2) }));
+}
+
+#[test]
+fn arithmetic_test() {
+    for input in &[vec![], vec![7], vec![1, 1, 1, 1], "abracadabra".as_bytes().to_vec()] {
+        assert_eq!(input, &decompression_arithmetic(&compression_arithmetic(input)).unwrap());
+        let coded = compression_adaptive_arithmetic(input.iter().cloned());
+        assert_eq!(input, &decompression_adaptive_arithmetic(&coded).unwrap());
+    }
+
+    // a skewed source, where Huffman cannot go below one bit per byte
+    let mut input = vec![b'a'; 10000];
+    for i in 0..100 {
+        input[i * 97] = b'b' + (i % 3) as u8;
+    }
+    let huffman = compression_huffman(&input);
+    let coded = compression_arithmetic(&input);
+    assert!(coded.len() * 4 < huffman.len());
+    assert_eq!(input, decompression_arithmetic(&coded).unwrap());
+    let coded = compression_adaptive_arithmetic(input.iter().cloned());
+    assert!(coded.len() * 4 < huffman.len());
+    assert_eq!(input, decompression_adaptive_arithmetic(&coded).unwrap());
+
+    // a huge count, with a lone symbol that would cost no bits (rejected with its model) or
+    // with more symbols than the code can hold
+    let statistics: BTreeMap<u8, u64> = vec![(7, 1)].into_iter().collect();
+    let mut lone = BitVec::new();
+    for &value in &[1, 8, 1] {
+        push_gamma(&mut lone, value);
+    }
+    let mut count = BitVec::new();
+    push_gamma(&mut count, 1 << 40);
+    let code = BitVec::from_elem(32, false);
+    for &(ref model, offset) in &[(lone, count.len()),
+                                  (StaticModel::from_statistics(&statistics).encode(), 0)] {
+        let coded = append_bit_vec(append_bit_vec(count.clone(), model), &code);
+        assert_eq!(decompression_arithmetic(&serialize_bit_vec(&coded)),
+                   Err(Error::InvalidHeader { offset: Offset::Bit(offset) }));
+        // the same models for the pointers and the characters of LZ78
+        let coded = append_bit_vec(append_bit_vec(append_bit_vec(count.clone(), model), model),
+                                   &code);
+        let mut content = vec![16, Policy::Freeze as u8, Entropy::Arithmetic as u8];
+        content.extend(serialize_bit_vec(&coded));
+        assert_eq!(decompression_lz78(&content),
+                   Err(Error::InvalidHeader { offset: Offset::Bit(offset) }));
+    }
+}
+
+#[test]
+fn ans_test() {
+    for input in &[vec![], vec![7], vec![1, 1, 1, 1], "abracadabra".as_bytes().to_vec()] {
+        assert_eq!(input, &decompression_rans(&compression_rans(input)).unwrap());
+        assert_eq!(input, &decompression_tans(&compression_tans(input)).unwrap());
+    }
+
+    let mut input = vec![b'a'; 10000];
+    for i in 0..100 {
+        input[i * 97] = b'b' + (i % 3) as u8;
+    }
+    let huffman = compression_huffman(&input);
+    for &(compression, decompression) in
+        &[(compression_rans as fn(&[u8]) -> Vec<u8>,
+           decompression_rans as fn(&[u8]) -> Result<Vec<u8>, Error>),
+          (compression_tans, decompression_tans)] {
+        let coded = compression(&input);
+        assert!(coded.len() * 4 < huffman.len());
+        assert_eq!(input, decompression(&coded).unwrap());
+        assert_eq!(decompression(&coded[..coded.len() - 4]).err().map(|error| match error {
+                       Error::UnexpectedEof { .. } => "eof",
+                       _ => "other",
+                   }),
+                   Some("eof"));
+    }
+
+    // a huge count, with a lone symbol that would cost no bits (rejected with its table) or
+    // with more symbols than the code can hold
+    let statistics: BTreeMap<u8, u64> = vec![(7, 1)].into_iter().collect();
+    let mut lone = BitVec::new();
+    push_bits(&mut lone, 11, 5);
+    for &value in &[1, 8, 1 << 11] {
+        push_gamma(&mut lone, value);
+    }
+    let mut count = BitVec::new();
+    push_gamma(&mut count, 1 << 40);
+    let code = BitVec::from_elem(64, false);
+    for &(ref table, offset) in &[(lone, count.len()),
+                                  (Frequencies::from_statistics(&statistics, 11).encode(), 0)] {
+        let coded = serialize_bit_vec(&append_bit_vec(append_bit_vec(count.clone(), table),
+                                                      &code));
+        for decompression in &[decompression_rans, decompression_tans] {
+            assert_eq!(decompression(&coded),
+                       Err(Error::InvalidHeader { offset: Offset::Bit(offset) }));
+        }
+        // the same tables for the pointers and the characters of LZ78
+        let coded = append_bit_vec(append_bit_vec(append_bit_vec(count.clone(), table), table),
+                                   &code);
+        for &entropy in &[Entropy::Rans, Entropy::Tans] {
+            let mut content = vec![16, Policy::Freeze as u8, entropy as u8];
+            content.extend(serialize_bit_vec(&coded));
+            assert_eq!(decompression_lz78(&content),
+                       Err(Error::InvalidHeader { offset: Offset::Bit(offset) }));
+        }
+    }
+}
+
+#[test]
+fn ppm_test() {
+    for input in &[vec![], vec![7], vec![1, 1, 1, 1], "abracadabra".as_bytes().to_vec()] {
+        assert_eq!(input, &decompression_ppm(&compression_ppm(input)).unwrap());
+    }
+
+    // on text, context modeling beats dictionary matching with order-0 coding
+    let input = "It is a truth universally acknowledged, that a single man in possession of a \
+                 good fortune, must be in want of a wife. "
+        .repeat(30)
+        .into_bytes();
+    let coded = compression_ppm(&input);
+    assert_eq!(&coded[..2], &[4, 0x80][..]);
+    let lz77 = compress(&input, &Registry::default().chain("lz77+huffman").unwrap());
+    assert!(coded.len() < lz77.len());
+    assert_eq!(input, decompression_ppm(&coded).unwrap());
+
+    let params = PpmParams::new(2, 50);
+    assert_eq!(input, decompression_ppm(&compression_ppm_with(&input, params)).unwrap());
+    assert_eq!(decompression_ppm(&[16, 1, 0]),
+               Err(Error::InvalidHeader { offset: Offset::Byte(0) }));
+}
+
+#[test]
+fn mtf_test() {
+    // a few bytes in turns: after their first occurrence, they are all 3 places back
+    let input = [10, 20, 30, 40].repeat(500);
+    let coded = compression_mtf(&input);
+    assert!(coded[4..].iter().all(|&index| index == 3));
+    assert_eq!(input, decompression_mtf(&coded).unwrap());
+    assert!(compression_huffman(&coded).len() < compression_huffman(&input).len());
+}
+
+#[test]
+fn rle_test() {
+    // long runs cost a few bytes, where LZ77 needs a token for every view length
+    let input = vec![1; 100_000];
+    let lz77 = compression_lz77(input.iter().cloned());
+    for coded in &[compression_packbits(&input), compression_escape_rle(&input)] {
+        assert!(coded.len() * 4 < lz77.len());
+    }
+    assert_eq!(input, decompression_packbits(&compression_packbits(&input)).unwrap());
+    assert_eq!(input, decompression_escape_rle(&compression_escape_rle(&input)).unwrap());
+
+    // before Huffman coding, on move-to-front output
+    let input = [10, 20, 20, 20, 20, 20, 20, 30].repeat(500);
+    let runs = compression_zero_runs(&compression_mtf(&input));
+    assert_eq!(input, decompression_mtf(&decompression_zero_runs(&runs).unwrap()).unwrap());
+    assert!(compression_huffman(&runs).len() < compression_huffman(&input).len());
+    assert_eq!(decompression_zero_runs(&[1, 255]),
+               Err(Error::UnexpectedEof { offset: Offset::Byte(1) }));
+    // the length in the header bounds the runs
+    assert!(decompression_zero_runs(&[1; 60]).is_err());
+    assert_eq!(decompression_zero_runs(&[3, 0]),
+               Err(Error::InvalidHeader { offset: Offset::Byte(0) }));
+}
+
+#[test]
+fn filter_test() {
+    assert_eq!(decompression_filter(&compression_filter(&[])).unwrap(), vec![]);
+
+    // a slow little-endian ramp of 32-bit samples
+    let input: Vec<u8> = (0..5000u32).flat_map(|i| (100_000 + 3 * i).to_le_bytes()).collect();
+    let registry = Registry::default();
+    let plain = compress(&input, &registry.chain("lz77+huffman").unwrap());
+    let params = FilterParams::new(FilterKind::Delta, 4);
+    let filtered = compression_filter_with(&input, params);
+    assert_eq!(filtered[0], 4);
+    let coded = compress(&filtered, &registry.chain("lz77+huffman").unwrap());
+    assert!(coded.len() * 4 < plain.len());
+    assert_eq!(input, decompression_filter(&filtered).unwrap());
+
+    // the parameters travel with the data, so any filter id decodes it
+    let coded = compress(&input, &registry.chain("xor+delta-4+huffman").unwrap());
+    assert_eq!(input, decompress(&coded).unwrap());
+    assert_eq!(decompression_filter(&[3, 1, 2]),
+               Err(Error::InvalidHeader { offset: Offset::Byte(0) }));
+}
+
+#[test]
+fn bwt_test() {
+    for input in &[vec![], vec![7], vec![1, 1, 1, 1], "abracadabra".as_bytes().to_vec()] {
+        assert_eq!(input, &decompression_bwt(&compression_bwt(input)).unwrap());
+    }
+
+    let input = "It is a truth universally acknowledged, that a single man in possession of a \
+                 good fortune, must be in want of a wife. "
+        .repeat(30)
+        .into_bytes();
+    let coded = compression_bwt(&input);
+    let lz77 = compress(&input, &Registry::default().chain("lz77+huffman").unwrap());
+    assert!(coded.len() < lz77.len());
+    assert_eq!(input, decompression_bwt(&coded).unwrap());
+
+    // every block has its own primary index and code
+    let coded = compression_bwt_with(&input, BwtParams::new(1000));
+    assert_eq!(&coded[..2], &[0xe8, 0x07][..]);
+    assert_eq!(input, decompression_bwt(&coded).unwrap());
+    let blocks = compression_bwt_with(&input, BwtParams::new(100));
+    assert!(blocks.len() > coded.len());
+    assert_eq!(input, decompression_bwt(&blocks).unwrap());
+}
+
+#[test]
+fn lzw_test() {
+    for input in &[vec![], vec![1], vec![1, 1, 1, 1], "abracadabra".as_bytes().to_vec()] {
+        assert_eq!(input, &decompression_lzw(&compression_lzw(input)).unwrap());
+    }
+
+    let input = "abracadabra abracadabra abracadabra".repeat(50).into_bytes();
+    for &params in &[LzwParams::new(9, true), LzwParams::new(12, false)] {
+        let coded = compression_lzw_with(input.iter().cloned(), params);
+        assert!(coded.len() < input.len() / 4);
+        assert_eq!(input, decompression_lzw(&coded).unwrap());
+    }
+
+    assert_eq!(decompression_lzw(&[8, 0]),
+               Err(Error::InvalidHeader { offset: Offset::Byte(0) }));
+}
+
+#[test]
+fn lz78_test() {
+    for input in &[vec![1], vec![1, 1], vec![1, 1, 1, 2], vec![0; 100]] {
+        assert_eq!(input, &decompression_lz78(&compression_lz78(input)).unwrap());
+    }
+
+    // code lengths rather than 64 bits for each pointer leaf
+    let input = "abracadabra abracadabra abracadabra".as_bytes().to_vec();
+    let coded = compression_lz78(&input);
+    assert!(coded.len() < input.len());
+    assert_eq!(input, decompression_lz78(&coded).unwrap());
+
+    // the parameters travel with the stream
+    let input = "abracadabra abracadabra abracadabra".repeat(20).into_bytes();
+    for &policy in &[Policy::Freeze, Policy::Reset, Policy::Lru] {
+        let coded = compression_lz78_with(&input, Lz78Params::new(16, policy));
+        assert_eq!(&coded[..2], &[16, policy as u8][..]);
+        assert_eq!(input, decompression_lz78(&coded).unwrap());
+    }
+    assert_eq!(decompression_lz78(&[0, 1, 0]),
+               Err(Error::InvalidHeader { offset: Offset::Byte(0) }));
+    assert_eq!(decompression_lz78(&[16, 3, 0]),
+               Err(Error::InvalidHeader { offset: Offset::Byte(1) }));
+
+    for &entropy in &[Entropy::Huffman, Entropy::Arithmetic, Entropy::Rans, Entropy::Tans] {
+        for input in &[vec![1], vec![1, 1, 1, 2], input.clone()] {
+            let params = Lz78Params::with_entropy(1 << 12, Policy::Lru, entropy);
+            let coded = compression_lz78_with(input, params);
+            assert_eq!(coded[3], entropy as u8);
+            assert_eq!(input, &decompression_lz78(&coded).unwrap());
+        }
+    }
+}
@@ -1,10 +1,17 @@
-use std::ops::Index;
+use std::borrow::Borrow;
+use std::ops::{Index, Range};
 use std::fmt::{Debug, Formatter, Error};
-use std::collections::VecDeque;
+use std::cmp;
+use std::collections::{HashMap, VecDeque};
+use std::hash::{BuildHasherDefault, Hasher};
 
+/// The last `size` values pushed, starting as `size` default values. Storage grows with the
+/// values actually pushed, so a large size costs nothing until it is used.
 struct Cycle<T> {
     data: Vec<T>,
+    size: usize,
     begin: usize,
+    default: T,
 }
 
 impl<T> Cycle<T>
@@ -12,22 +19,36 @@ impl<T> Cycle<T>
 {
     fn new(size: usize) -> Cycle<T> {
         Cycle {
-            data: vec![T::default(); size],
+            data: Vec::new(),
+            size,
             begin: 0,
+            default: T::default(),
         }
     }
 
+    fn len(&self) -> usize {
+        self.size
+    }
+
     fn push(&mut self, x: T) {
+        if self.data.len() < self.size {
+            self.data.push(x);
+            return;
+        }
         self.data[self.begin] = x;
-        self.begin = (self.begin + 1) % self.data.len();
+        self.begin = (self.begin + 1) % self.size;
     }
 
-    fn modulo(&self, mut i: isize) -> usize {
-        i = (self.begin as isize + i) % self.data.len() as isize;
-        if i < 0 {
-            i += self.data.len() as isize;
+    fn modulo(&self, i: isize) -> usize {
+        let (i, size) = (self.begin as isize + i, self.size as isize);
+        // indexes are almost always within one turn, which saves a division
+        if i >= size && i < 2 * size {
+            (i - size) as usize
+        } else if i >= 0 && i < size {
+            i as usize
+        } else {
+            i.rem_euclid(size) as usize
         }
-        i as usize
     }
 }
 
@@ -36,7 +57,12 @@ impl<T> Index<isize> for Cycle<T>
 {
     type Output = T;
     fn index(&self, index: isize) -> &T {
-        &self.data[self.modulo(index)]
+        // until the cycle is full, the default values come first
+        let missing = self.size - self.data.len();
+        match self.modulo(index) {
+            i if i < missing => &self.default,
+            i => &self.data[i - missing],
+        }
     }
 }
 
@@ -44,36 +70,563 @@ impl<T> Debug for Cycle<T>
     where T: Default + Clone + Debug
 {
     fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
-        write!(f, "{:?}", &self.data[self.begin..])?;
-        write!(f, "{:?}", &self.data[..self.begin])
+        let values: Vec<&T> = (0..self.size as isize).map(|i| &self[i]).collect();
+        write!(f, "{:?}", values)
+    }
+}
+
+pub const WINDOW_SIZE: usize = 4096;
+pub const VIEW_SIZE: usize = 16;
+pub const MAX_WINDOW_SIZE: usize = 1 << 30;
+pub const MAX_VIEW_SIZE: usize = 1 << 16;
+
+/// The window holds the last `window_size` bytes, lookahead included, and matches are
+/// shorter than the lookahead of `view_size` bytes: tokens are `(ptr, len, byte)` with
+/// `ptr < window_size` and `len < view_size`.
+///
+/// `max_chain` bounds how many earlier positions the encoder tries for each match, the
+/// nearest first, trading ratio for speed. With `None` it always finds the longest match,
+/// but may try every position of the window for each match: on repetitive data with a large
+/// window, encoding is quadratic. `Some(n)` keeps it linear. `parse` chooses the
+/// tokens among the matches found. Neither is part of the stream.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Lz77Params {
+    pub window_size: usize,
+    pub view_size: usize,
+    pub max_chain: Option<usize>,
+    pub parse: Parse,
+}
+
+impl Default for Lz77Params {
+    fn default() -> Lz77Params {
+        Lz77Params {
+            window_size: WINDOW_SIZE,
+            view_size: VIEW_SIZE,
+            max_chain: None,
+            parse: Parse::Greedy,
+        }
+    }
+}
+
+impl Lz77Params {
+    pub fn new(window_size: usize, view_size: usize) -> Lz77Params {
+        let params = Lz77Params {
+            window_size,
+            view_size,
+            max_chain: None,
+            parse: Parse::Greedy,
+        };
+        assert!(params.is_valid(),
+                "invalid LZ77 parameters: window {}, view {}",
+                window_size,
+                view_size);
+        params
+    }
+
+    pub fn is_valid(&self) -> bool {
+        self.view_size >= 1 && self.view_size <= MAX_VIEW_SIZE &&
+        self.window_size > self.view_size && self.window_size <= MAX_WINDOW_SIZE
+    }
+
+    /// Bits of `len` and bytes of `ptr << len_bits | len` in a serialized token.
+    pub fn token_layout(&self) -> (u32, usize) {
+        fn bits(max: usize) -> u32 {
+            usize::BITS - max.leading_zeros()
+        }
+        let len_bits = bits(self.view_size - 1);
+        let ptr_bits = bits(self.window_size - 1);
+        (len_bits, (ptr_bits + len_bits).div_ceil(8) as usize)
+    }
+
+    fn parse_block(&self) -> usize {
+        cmp::max(PARSE_BLOCK, 4 * self.view_size)
+    }
+
+    /// Bytes the encoder reads ahead of the data it codes.
+    pub fn lookahead(&self) -> usize {
+        match self.parse {
+            Parse::Greedy => self.view_size,
+            Parse::Lazy => self.view_size + 1,
+            Parse::Optimal => self.parse_block() + self.view_size,
+        }
     }
 }
 
-const WINDOW_SIZE: usize = 4096;
-const VIEW_SIZE: usize = 16;
+/// Positions parsed together by `Parse::Optimal`, at least.
+const PARSE_BLOCK: usize = 1 << 14;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Parse {
+    /// Takes the longest match at each position.
+    Greedy,
+    /// Emits a lone literal instead when the next position has a longer match.
+    Lazy,
+    /// Chooses, by dynamic programming over the longest match at every position of a block,
+    /// the tokens that code the block in the fewest bits. A token is priced by the entropy
+    /// of its bytes in a first parse, which is what an entropy coder after LZ77 pays.
+    Optimal,
+}
+
+/// Hashes the keys of `MatchFinder`, at most 3 bytes, with a multiplication: much faster
+/// than the default hasher, which shows when every byte read updates two tables.
+#[derive(Default)]
+struct KeyHasher(u64);
+
+impl Hasher for KeyHasher {
+    fn finish(&self) -> u64 {
+        self.0 ^ self.0 >> 32
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.write_u32(byte as u32);
+        }
+    }
+
+    fn write_u32(&mut self, key: u32) {
+        self.0 = (self.0 ^ key as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15);
+    }
+}
+
+type Positions = HashMap<u32, VecDeque<usize>, BuildHasherDefault<KeyHasher>>;
+
+/// The last `window_size` bytes read, lookahead included, with the positions where each
+/// byte value, pair of bytes and triple of bytes starts in the window, oldest first. The
+/// window starts filled with zeros, which the decoder mirrors, so matches may point before
+/// the first byte; those initial zeros are not in the lists but counted by `initial_zeros`.
+/// Positions are absolute: window index + `readed`.
+struct MatchFinder {
+    window: Cycle<u8>,
+    positions: Vec<VecDeque<usize>>,
+    pairs: Positions,
+    chains: Positions,
+    max_chain: Option<usize>,
+    readed: usize,
+    /// The last three bytes of the window.
+    recent: u32,
+}
+
+impl MatchFinder {
+    fn new(window_size: usize, max_chain: Option<usize>) -> MatchFinder {
+        MatchFinder {
+            window: Cycle::new(window_size),
+            positions: vec![VecDeque::new(); 256],
+            pairs: Positions::default(),
+            chains: Positions::default(),
+            max_chain,
+            readed: 0,
+            recent: 0,
+        }
+    }
+
+    fn window_size(&self) -> usize {
+        self.window.len()
+    }
+
+    fn key(&self, start: isize, len: usize) -> u32 {
+        (0..len).fold(0, |key, i| key << 8 | self.window[start + i as isize] as u32)
+    }
+
+    /// Positions still in the window where `len` of the initial zeros start.
+    fn initial_zeros(&self, len: usize) -> Range<usize> {
+        self.readed..cmp::max(self.readed, (self.window_size() + 1).saturating_sub(len))
+    }
+
+    /// Positions where the `len` bytes of `key` start, oldest first.
+    fn starts<'a>(&'a self, table: &'a Positions, key: u32, len: usize)
+                  -> impl DoubleEndedIterator<Item = usize> + 'a {
+        let zeros = if key == 0 { self.initial_zeros(len) } else { 0..0 };
+        zeros.chain(table.get(&key).into_iter().flatten().cloned())
+    }
+
+    fn push(&mut self, byte: u8) {
+        let window_size = self.window_size();
+
+        // the oldest position leaves the window, it is at the front of its lists
+        let oldest = self.readed;
+        // before that, only initial zeros leave
+        if oldest + 2 >= window_size {
+            let triple = self.key(0, 3);
+            let positions = &mut self.positions[(triple >> 16) as usize];
+            if positions.front() == Some(&oldest) {
+                positions.pop_front();
+            }
+            remove_oldest(&mut self.pairs, triple >> 8, oldest);
+            remove_oldest(&mut self.chains, triple, oldest);
+        }
+
+        self.window.push(byte);
+        self.recent = (self.recent << 8 | byte as u32) & 0xff_ffff;
+        let position = window_size + self.readed;
+        self.positions[byte as usize].push_back(position);
+        self.pairs.entry(self.recent & 0xffff).or_default().push_back(position - 1);
+        // a triple must still be in the window when its first byte leaves
+        if window_size >= 3 {
+            self.chains.entry(self.recent).or_default().push_back(position - 2);
+        }
+        self.readed += 1;
+    }
+
+    /// Length of the match at window index `j` for the data starting at window index
+    /// `start`: common bytes, not overlapping `start`, at most `max_len`.
+    fn match_len(&self, j: usize, start: usize, max_len: usize) -> usize {
+        let limit = cmp::min(max_len, start - j);
+        let mut len = 0;
+        while len < limit &&
+              self.window[(j + len) as isize] == self.window[(start + len) as isize] {
+            len += 1;
+        }
+        len
+    }
+
+    /// Finds the longest match of at most `max_len` bytes for the data starting `to_code`
+    /// bytes before the end of the window, and returns it as `(ptr, len)` with `ptr` the
+    /// distance back to the match (0 if there is none). A match never overlaps the data it
+    /// encodes.
+    ///
+    /// Matches of 3 bytes or more are searched along the positions of the first three bytes:
+    /// all of them, oldest first, without `max_chain`, so that the oldest of the longest
+    /// matches wins; otherwise the `max_chain` newest ones, the nearest match winning ties.
+    /// Shorter matches are the oldest positions of the first byte or pair.
+    fn find(&self, to_code: usize, max_len: usize) -> (usize, usize) {
+        let start = self.window_size() - to_code;
+
+        // -----WINDOW-----|VIEW
+        // 0               ^-- start, size to_code
+
+        let (mut ptr, mut len) = (0, 0);
+
+        if max_len >= 3 && to_code >= 3 {
+            let candidates = self.starts(&self.chains, self.key(start as isize, 3), 3);
+            match self.max_chain {
+                None => {
+                    for position in candidates {
+                        let j = position - self.readed;
+                        // later positions are closer, so their matches cannot be longer
+                        if j + cmp::max(len, 2) >= start {
+                            break;
+                        }
+                        let found = self.match_len(j, start, max_len);
+                        if found > len {
+                            ptr = start - j;
+                            len = found;
+                            if len == max_len {
+                                break;
+                            }
+                        }
+                    }
+                }
+                Some(depth) => {
+                    // the newest positions may be in the data to code, too close for a match
+                    let readed = self.readed;
+                    let earlier = candidates.rev()
+                        .skip_while(|&position| position - readed + 3 > start);
+                    for position in earlier.take(depth) {
+                        let mut j = position - self.readed;
+                        if j + len >= start {
+                            continue;
+                        }
+                        let mut found = self.match_len(j, start, max_len);
+                        // a match cut short by the data it would overlap repeats with period
+                        // `start - j`: whole periods further back, it may run longer
+                        let period = start - j;
+                        if found == period && found < max_len {
+                            let back = period * cmp::min(max_len.div_ceil(period), start / period);
+                            let longer = self.match_len(start - back, start, max_len);
+                            if longer > found {
+                                j = start - back;
+                                found = longer;
+                            }
+                        }
+                        if found > len {
+                            ptr = start - j;
+                            len = found;
+                            if len == max_len {
+                                break;
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        if len >= 3 {
+            return (ptr, len);
+        }
+
+        let first = self.window[start as isize];
+        if max_len >= 2 && to_code >= 2 {
+            let key = self.key(start as isize, 2);
+            if let Some(position) = self.starts(&self.pairs, key, 2).next() {
+                if position - self.readed + 2 <= start {
+                    return (start - (position - self.readed), 2);
+                }
+            }
+        }
+        if max_len >= 1 {
+            let zeros = if first == 0 { self.initial_zeros(1) } else { 0..0 };
+            if let Some(position) = zeros.chain(self.positions[first as usize].iter().cloned())
+                .next() {
+                if position - self.readed < start {
+                    return (start - (position - self.readed), 1);
+                }
+            }
+        }
+        (0, 0)
+    }
+}
+
+fn remove_oldest(table: &mut Positions, key: u32, oldest: usize) {
+    let empty = match table.get_mut(&key) {
+        Some(list) if list.front() == Some(&oldest) => {
+            list.pop_front();
+            list.is_empty()
+        }
+        _ => false,
+    };
+    if empty {
+        table.remove(&key);
+    }
+}
 
 pub struct LZ77CodingIter<I>
     where I: Iterator<Item = u8>
 {
     iter: I,
-    window: Cycle<u8>,
-    positions: Vec<VecDeque<usize>>,
-    readed: usize,
+    params: Lz77Params,
+    finder: MatchFinder,
     to_code: usize,
+    tokens: VecDeque<(usize, usize, u8)>,
+    // for `Parse::Optimal`: longest match and byte at the positions read but not coded
+    matches: Vec<((usize, usize), u8)>,
+}
+
+impl<I> LZ77CodingIter<I>
+    where I: Iterator<Item = u8>
+{
+    fn fill(&mut self, lookahead: usize) {
+        while self.to_code < lookahead {
+            if let Some(byte) = self.iter.next() {
+                self.finder.push(byte);
+                self.to_code += 1;
+            } else {
+                break;
+            }
+        }
+    }
+
+    /// Longest match for the data `to_code` bytes before the end of the window, keeping at
+    /// least one byte for the literal.
+    fn longest_match(&self, to_code: usize) -> (usize, usize) {
+        self.finder.find(to_code, cmp::min(to_code, self.params.view_size) - 1)
+    }
+
+    fn token(&mut self, ptr: usize, len: usize) -> (usize, usize, u8) {
+        let k = self.finder.window[len as isize - self.to_code as isize];
+        self.to_code -= len + 1;
+        (ptr, len, k)
+    }
+
+    fn parse_block(&mut self) {
+        // longest match and byte at each position not coded yet
+        let view_size = self.params.view_size;
+        let mut ended = false;
+        while self.matches.len() < self.params.parse_block() {
+            self.fill(view_size);
+            if self.to_code == 0 {
+                ended = true;
+                break;
+            }
+            let found = self.longest_match(self.to_code);
+            self.matches.push((found, self.finder.window[-(self.to_code as isize)]));
+            self.to_code -= 1;
+        }
+
+        // every token has the same size, so the first parse minimizes their number; the
+        // second one prices them by the frequencies of the bytes the first one produced
+        let uniform = self.parse(|_| 1);
+        let (len_bits, ptr_len_bytes) = self.params.token_layout();
+        let mut counts = [1u64; 256];
+        for &(ptr, len, byte) in &uniform {
+            let ptr_len = (ptr as u64) << len_bits | len as u64;
+            for i in 0..ptr_len_bytes {
+                counts[(ptr_len >> (8 * i)) as u8 as usize] += 1;
+            }
+            counts[byte as usize] += 1;
+        }
+        let total = counts.iter().sum::<u64>() as f64;
+        // in sixteenths of a bit
+        let prices: Vec<u64> = counts.iter()
+            .map(|&count| (-(count as f64 / total).log2() * 16.0) as u64 + 1)
+            .collect();
+        let tokens = self.parse(|(ptr, len, byte)| {
+            let ptr_len = (ptr as u64) << len_bits | len as u64;
+            (0..ptr_len_bytes).map(|i| prices[(ptr_len >> (8 * i)) as u8 as usize]).sum::<u64>() +
+            prices[byte as usize]
+        });
+
+        // the end of the block is parsed again with the next one, unless the input ended
+        let keep = if ended { 0 } else { view_size };
+        let mut coded = 0;
+        for token in tokens {
+            if coded + keep >= self.matches.len() {
+                break;
+            }
+            coded += token.1 + 1;
+            self.tokens.push_back(token);
+        }
+        self.matches.drain(..coded);
+    }
+
+    /// Cheapest tokens for `matches` according to `cost`; ties go to the longest match.
+    fn parse<F>(&self, cost: F) -> Vec<(usize, usize, u8)>
+        where F: Fn((usize, usize, u8)) -> u64
+    {
+        let n = self.matches.len();
+        let token = |p: usize, len: usize| {
+            let ptr = if len > 0 { (self.matches[p].0).0 } else { 0 };
+            (ptr, len, self.matches[p + len].1)
+        };
+        let mut costs = vec![0; n + 1];
+        let mut lens = vec![0; n];
+        for p in (0..n).rev() {
+            let longest = cmp::min((self.matches[p].0).1, n - 1 - p);
+            let (best_cost, best_len) = (0..longest + 1)
+                .rev()
+                .map(|len| (cost(token(p, len)) + costs[p + len + 1], len))
+                .min_by_key(|&(cost, _)| cost)
+                .unwrap();
+            costs[p] = best_cost;
+            lens[p] = best_len;
+        }
+
+        let mut tokens = Vec::new();
+        let mut p = 0;
+        while p < n {
+            tokens.push(token(p, lens[p]));
+            p += lens[p] + 1;
+        }
+        tokens
+    }
 }
 
 impl<I> Iterator for LZ77CodingIter<I>
     where I: Iterator<Item = u8>
 {
-    type Item = (u16, u8, u8);
-    fn next(&mut self) -> Option<(u16, u8, u8)> {
-        while self.to_code < VIEW_SIZE {
+    type Item = (usize, usize, u8);
+    fn next(&mut self) -> Option<(usize, usize, u8)> {
+        if self.params.parse == Parse::Optimal {
+            while self.tokens.is_empty() {
+                self.parse_block();
+                if self.matches.is_empty() {
+                    break;
+                }
+            }
+            return self.tokens.pop_front();
+        }
+
+        let lookahead = self.params.lookahead();
+        self.fill(lookahead);
+        if self.to_code == 0 {
+            return None;
+        }
+
+        let (ptr, len) = self.longest_match(self.to_code);
+        if self.params.parse == Parse::Lazy && len > 0 && self.to_code > 1 &&
+           self.longest_match(self.to_code - 1).1 > len {
+            return Some(self.token(0, 0));
+        }
+        Some(self.token(ptr, len))
+    }
+}
+
+#[allow(dead_code)]
+pub fn lz77_coding<I>(iter: I, params: Lz77Params) -> LZ77CodingIter<I>
+    where I: Iterator<Item = u8>
+{
+    assert!(params.is_valid(), "invalid LZ77 parameters {:?}", params);
+    LZ77CodingIter {
+        iter,
+        params,
+        finder: MatchFinder::new(params.window_size, params.max_chain),
+        to_code: 0,
+        tokens: VecDeque::new(),
+        matches: Vec::new(),
+    }
+}
+
+/**************************************************************************************************
+ LZSS
+*/
+
+/// LZSS keeps the LZ77 window, but a match is only emitted when it is at least
+/// `min_match` bytes long, and a token is either a literal or a match, never both.
+/// Matches are at most `view_size` bytes long. `max_chain` is as for `Lz77Params`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LzssParams {
+    pub window_size: usize,
+    pub view_size: usize,
+    pub min_match: usize,
+    pub max_chain: Option<usize>,
+}
+
+impl Default for LzssParams {
+    fn default() -> LzssParams {
+        LzssParams {
+            window_size: WINDOW_SIZE,
+            view_size: 18,
+            min_match: 3,
+            max_chain: None,
+        }
+    }
+}
+
+impl LzssParams {
+    pub fn new(window_size: usize, view_size: usize, min_match: usize) -> LzssParams {
+        let params = LzssParams {
+            window_size,
+            view_size,
+            min_match,
+            max_chain: None,
+        };
+        assert!(params.is_valid(), "invalid LZSS parameters {:?}", params);
+        params
+    }
+
+    pub fn is_valid(&self) -> bool {
+        let window = Lz77Params {
+            window_size: self.window_size,
+            view_size: self.view_size,
+            max_chain: self.max_chain,
+            parse: Parse::Greedy,
+        };
+        window.is_valid() && self.min_match >= 1 && self.min_match <= self.view_size
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LzssToken {
+    Literal(u8),
+    Match { distance: usize, length: usize },
+}
+
+pub struct LzssCodingIter<I>
+    where I: Iterator<Item = u8>
+{
+    iter: I,
+    params: LzssParams,
+    finder: MatchFinder,
+    to_code: usize,
+}
+
+impl<I> Iterator for LzssCodingIter<I>
+    where I: Iterator<Item = u8>
+{
+    type Item = LzssToken;
+    fn next(&mut self) -> Option<LzssToken> {
+        while self.to_code < self.params.view_size {
             if let Some(byte) = self.iter.next() {
-                self.positions[self.window[0] as usize].pop_front();
-                self.window.push(byte);
-                self.positions[byte as usize].push_back(WINDOW_SIZE + self.readed);
+                self.finder.push(byte);
                 self.to_code += 1;
-                self.readed += 1;
             } else {
                 break;
             }
@@ -83,57 +636,80 @@ impl<I> Iterator for LZ77CodingIter<I>
             return None;
         }
 
-        // -----WINDOW-----|VIEW
-        // 0                 ^-- size to_code
+        let (distance, length) = self.finder.find(self.to_code, self.to_code);
+        if length >= self.params.min_match {
+            self.to_code -= length;
+            Some(LzssToken::Match { distance, length })
+        } else {
+            let byte = self.finder.window[-(self.to_code as isize)];
+            self.to_code -= 1;
+            Some(LzssToken::Literal(byte))
+        }
+    }
+}
 
-        let mut len: usize = 0;
-        let mut ptr: Option<u16> = None;
+#[allow(dead_code)]
+pub fn lzss_coding<I>(iter: I, params: LzssParams) -> LzssCodingIter<I>
+    where I: Iterator<Item = u8>
+{
+    assert!(params.is_valid(), "invalid LZSS parameters {:?}", params);
+    LzssCodingIter {
+        iter,
+        params,
+        finder: MatchFinder::new(params.window_size, params.max_chain),
+        to_code: 0,
+    }
+}
 
-        if self.to_code > 1 {
-            'b: for j in &self.positions[self.window[-(self.to_code as isize)] as usize] {
-                let j = j - self.readed;
+pub struct LzssDecodingIter<I>
+    where I: Iterator,
+          I::Item: Borrow<LzssToken>
+{
+    iter: I,
+    window: Cycle<u8>,
+    decoded: usize,
+}
 
-            // 'b: for j in 0..WINDOW_SIZE - self.to_code {
-                if j + len == WINDOW_SIZE - self.to_code {
-                    break 'b;
-                }
-                for i in 0..len {
-                    if self.window[j as isize + i as isize] !=
-                       self.window[i as isize - self.to_code as isize] {
-                        continue 'b;
-                    }
+impl<I> Iterator for LzssDecodingIter<I>
+    where I: Iterator,
+          I::Item: Borrow<LzssToken>
+{
+    type Item = u8;
+    fn next(&mut self) -> Option<u8> {
+        if self.decoded == 0 {
+            match self.iter.next().map(|token| *token.borrow()) {
+                Some(LzssToken::Literal(byte)) => {
+                    self.window.push(byte);
+                    self.decoded = 1;
                 }
-                while j + len < WINDOW_SIZE - self.to_code &&
-                      self.window[j as isize + len as isize] ==
-                      self.window[len as isize - self.to_code as isize] {
-                    ptr = Some((WINDOW_SIZE - j - self.to_code) as u16);
-                    len += 1;
-                    if len + 1 == self.to_code {
-                        break 'b;
+                Some(LzssToken::Match { distance, length }) => {
+                    let window_size = self.window.len();
+                    for _ in 0..length {
+                        let byte = self.window[window_size as isize - distance as isize];
+                        self.window.push(byte);
                     }
+                    self.decoded = length;
                 }
+                None => return None,
             }
         }
 
-        let k = self.window[len as isize - self.to_code as isize];
-        self.to_code -= len + 1;
-
-        Some((ptr.unwrap_or(0), len as u8, k))
+        let result = self.window[-(self.decoded as isize)];
+        self.decoded -= 1;
+        Some(result)
     }
 }
 
 #[allow(dead_code)]
-pub fn lz77_coding<I>(iter: I) -> LZ77CodingIter<I>
-    where I: Iterator<Item = u8>
+pub fn lzss_decoding<I>(iter: I, params: LzssParams) -> LzssDecodingIter<I>
+    where I: Iterator,
+          I::Item: Borrow<LzssToken>
 {
-    let mut positions = vec![VecDeque::new(); 256];
-    positions[0] = (0..WINDOW_SIZE).collect();
-    LZ77CodingIter {
-        iter: iter,
-        window: Cycle::new(WINDOW_SIZE),
-        positions: positions,
-        readed: 0,
-        to_code: 0,
+    assert!(params.is_valid(), "invalid LZSS parameters {:?}", params);
+    LzssDecodingIter {
+        iter,
+        window: Cycle::new(params.window_size),
+        decoded: 0,
     }
 }
 
@@ -142,27 +718,40 @@ pub fn lz77_coding<I>(iter: I) -> LZ77CodingIter<I>
 */
 
 
-pub struct LZ77DecodingIter<'a, I>
-    where I: Iterator<Item = &'a (u16, u8, u8)>
+pub struct LZ77DecodingIter<I>
+    where I: Iterator,
+          I::Item: Borrow<(usize, usize, u8)>
 {
     iter: I,
+    window_size: usize,
     window: Cycle<u8>,
     decoded: usize,
 }
 
-impl<'a, I> Iterator for LZ77DecodingIter<'a, I>
-    where I: Iterator<Item = &'a (u16, u8, u8)>
+impl<I> LZ77DecodingIter<I>
+    where I: Iterator,
+          I::Item: Borrow<(usize, usize, u8)>
+{
+    pub fn get_mut(&mut self) -> &mut I {
+        &mut self.iter
+    }
+}
+
+impl<I> Iterator for LZ77DecodingIter<I>
+    where I: Iterator,
+          I::Item: Borrow<(usize, usize, u8)>
 {
     type Item = u8;
     fn next(&mut self) -> Option<u8> {
         if self.decoded == 0 {
-            if let Some(&(ptr, len, byte)) = self.iter.next() {
+            if let Some(token) = self.iter.next() {
+                let (ptr, len, byte) = *token.borrow();
                 for _ in 0..len {
-                    let byte = self.window[WINDOW_SIZE as isize - ptr as isize];
+                    let byte = self.window[self.window_size as isize - ptr as isize];
                     self.window.push(byte);
                 }
                 self.window.push(byte);
-                self.decoded += len as usize + 1;
+                self.decoded += len + 1;
             } else {
                 return None;
             }
@@ -175,26 +764,75 @@ impl<'a, I> Iterator for LZ77DecodingIter<'a, I>
 }
 
 #[allow(dead_code)]
-pub fn lz77_decoding<'a, I>(iter: I) -> LZ77DecodingIter<'a, I>
-    where I: Iterator<Item = &'a (u16, u8, u8)>
+pub fn lz77_decoding<I>(iter: I, params: Lz77Params) -> LZ77DecodingIter<I>
+    where I: Iterator,
+          I::Item: Borrow<(usize, usize, u8)>
 {
+    assert!(params.is_valid(), "invalid LZ77 parameters {:?}", params);
     LZ77DecodingIter {
-        iter: iter,
-        window: Cycle::new(WINDOW_SIZE),
+        iter,
+        window_size: params.window_size,
+        window: Cycle::new(params.window_size),
         decoded: 0,
     }
 }
 
+#[test]
+fn lzss_testing() {
+    let input = r#"[package]
+name = "data_compression"
+version = "0.1.0"
+authors = ["lcolbois"]"#
+        .as_bytes()
+        .to_vec();
+    let params = [LzssParams::default(),
+                  LzssParams::new(8, 4, 1),
+                  LzssParams::new(1 << 16, 300, 5)];
+    for &params in &params {
+        let coded: Vec<_> = lzss_coding(input.iter().cloned(), params).collect();
+        for token in &coded {
+            if let LzssToken::Match { distance, length } = *token {
+                assert!(distance > 0 && distance < params.window_size);
+                assert!(length >= params.min_match && length <= params.view_size);
+            }
+        }
+        let decoded: Vec<u8> = lzss_decoding(coded.iter(), params).collect();
+        assert_eq!(input, decoded);
+    }
+
+    let coded: Vec<_> = lzss_coding(vec![7; 20].into_iter(), LzssParams::new(64, 8, 3)).collect();
+    assert_eq!(coded,
+               vec![LzssToken::Literal(7),
+                    LzssToken::Literal(7),
+                    LzssToken::Literal(7),
+                    LzssToken::Match { distance: 3, length: 3 },
+                    LzssToken::Match { distance: 6, length: 6 },
+                    LzssToken::Match { distance: 12, length: 8 }]);
+}
+
 #[test]
 fn lz_77_testing() {
     fn test(input: Vec<u8>) {
-        // println!("Input {:?}", input);
-        let coded: Vec<_> = lz77_coding(input.iter().cloned()).collect();
-        // println!("Coded {:?}", coded);
-        let decoded: Vec<u8> = lz77_decoding(coded.iter()).collect();
+        let limited = Lz77Params { max_chain: Some(4), ..Lz77Params::new(1 << 16, 300) };
+        let lazy = Lz77Params { parse: Parse::Lazy, ..Lz77Params::new(5, 3) };
+        let optimal = Lz77Params { parse: Parse::Optimal, ..Lz77Params::new(1 << 12, 40) };
+        let params = [Lz77Params::default(),
+                      Lz77Params::new(5, 3),
+                      Lz77Params::new(1 << 16, 300),
+                      limited,
+                      lazy,
+                      optimal];
+        for &params in &params {
+            // println!("Input {:?}", input);
+            let coded: Vec<_> = lz77_coding(input.iter().cloned(), params).collect();
+            // println!("Coded {:?}", coded);
+            assert!(coded.iter()
+                .all(|&(ptr, len, _)| ptr < params.window_size && len < params.view_size));
+            let decoded: Vec<u8> = lz77_decoding(coded.iter(), params).collect();
 
-        // println!("Decoded {:?}", decoded);
-        assert_eq!(input, decoded);
+            // println!("Decoded {:?}", decoded);
+            assert_eq!(input, decoded);
+        }
     }
     test(vec![]);
     test(vec![1]);
@@ -214,6 +852,19 @@ fn lz_77_testing() {
               52, 46, 52, 32, 40, 114, 101, 103, 105, 115, 116, 114, 121, 43, 104, 116, 116, 112,
               115, 58, 47, 47, 103, 105, 116, 104, 117, 98, 46, 99, 111, 109, 47, 114, 117, 115,
               116, 45, 108, 97, 110]);
+
+    // without a limit, the oldest of the longest matches wins; with one, the nearest
+    let input = b"abcdXabcdYabcd!".to_vec();
+    let limited = Lz77Params { max_chain: Some(1), ..Lz77Params::new(64, 8) };
+    for &(params, ptr) in &[(Lz77Params::new(64, 8), 10), (limited, 5)] {
+        let coded: Vec<_> = lz77_coding(input.iter().cloned(), params).collect();
+        assert_eq!(coded.last(), Some(&(ptr, 4, b'!')));
+    }
+    // runs are matched about as far back as without a limit, though their nearest positions
+    // overlap them
+    let limited = Lz77Params { max_chain: Some(2), ..Lz77Params::new(64, 16) };
+    let tokens = lz77_coding(vec![1; 100].into_iter(), limited).count();
+    assert!(tokens <= lz77_coding(vec![1; 100].into_iter(), Lz77Params::new(64, 16)).count() + 1);
     for j in 0..12 {
         let mut xs = Vec::new();
         let mut x: u32 = 0;
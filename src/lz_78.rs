@@ -1,58 +1,234 @@
-use std::collections::BTreeMap;
+use ans::MAX_TABLE_LOG;
+use arithmetic::MAX_TOTAL;
+use error::{Error, Offset};
+use std::collections::{BTreeSet, HashMap};
 
-#[allow(dead_code)]
-pub fn lz78_coding<'a, I>(iter: I) -> Vec<(u64, u8)>
+pub const MAX_ENTRIES: usize = 1 << 32;
+
+/// What happens to a new word once the dictionary holds `max_entries` words.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Policy {
+    /// Keep the dictionary as it is.
+    Freeze,
+    /// Empty the dictionary; the new word is dropped.
+    Reset,
+    /// Replace the least recently used word that is not the prefix of another one.
+    Lru,
+}
+
+/// How the pointers and bytes of the pairs are coded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Entropy {
+    Huffman,
+    Arithmetic,
+    Rans,
+    Tans,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Lz78Params {
+    pub max_entries: usize,
+    pub policy: Policy,
+    pub entropy: Entropy,
+}
+
+impl Default for Lz78Params {
+    fn default() -> Lz78Params {
+        Lz78Params {
+            max_entries: 1 << 16,
+            policy: Policy::Reset,
+            entropy: Entropy::Huffman,
+        }
+    }
+}
+
+impl Lz78Params {
+    pub fn new(max_entries: usize, policy: Policy) -> Lz78Params {
+        Lz78Params::with_entropy(max_entries, policy, Entropy::Huffman)
+    }
+
+    pub fn with_entropy(max_entries: usize, policy: Policy, entropy: Entropy) -> Lz78Params {
+        let params = Lz78Params {
+            max_entries,
+            policy,
+            entropy,
+        };
+        assert!(params.is_valid(), "invalid LZ78 parameters {:?}", params);
+        params
+    }
+
+    /// The static models of the entropy coders must hold every pointer, up to `max_entries`.
+    pub fn is_valid(&self) -> bool {
+        let max_entries = match self.entropy {
+            Entropy::Huffman => MAX_ENTRIES,
+            Entropy::Arithmetic => MAX_TOTAL as usize / 2 - 2,
+            Entropy::Rans | Entropy::Tans => (1 << MAX_TABLE_LOG) - 1,
+        };
+        (1..=max_entries).contains(&self.max_entries)
+    }
+}
+
+enum Change {
+    None,
+    Reset,
+    /// The new word got this index; with LRU, it may have belonged to the given word.
+    Set(u64, Option<(u64, u8)>),
+}
+
+/// The dictionary as a trie stored by parent pointers: word `i` is word `parents[i]` followed
+/// by `bytes[i]`. It is shared by the coder and the decoder so that both apply the policy the
+/// same way. Index 0 is the empty word and is never removed.
+struct Entries {
+    params: Lz78Params,
+    parents: Vec<u64>,
+    bytes: Vec<u8>,
+    children: Vec<u64>,
+    uses: Vec<u64>,
+    /// Words without children, by last use (only for `Policy::Lru`).
+    leaves: BTreeSet<(u64, u64)>,
+    clock: u64,
+}
+
+impl Entries {
+    fn new(params: Lz78Params) -> Entries {
+        Entries {
+            params,
+            parents: vec![0],
+            bytes: vec![0],
+            children: vec![0],
+            uses: vec![0],
+            leaves: BTreeSet::new(),
+            clock: 0,
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.parents.len()
+    }
+
+    /// Records that the word `pointer` was just used as a prefix.
+    fn touch(&mut self, pointer: u64) {
+        self.clock += 1;
+        let p = pointer as usize;
+        if self.params.policy == Policy::Lru && pointer != 0 && self.children[p] == 0 {
+            self.leaves.remove(&(self.uses[p], pointer));
+            self.leaves.insert((self.clock, pointer));
+        }
+        self.uses[p] = self.clock;
+    }
+
+    /// Adds the word `parent` followed by `byte` if the policy makes room for it.
+    fn add(&mut self, parent: u64, byte: u8) -> Change {
+        let (index, evicted) = if self.len() <= self.params.max_entries {
+            self.parents.push(parent);
+            self.bytes.push(byte);
+            self.children.push(0);
+            self.uses.push(0);
+            (self.len() as u64 - 1, None)
+        } else {
+            match self.params.policy {
+                Policy::Freeze => return Change::None,
+                Policy::Reset => {
+                    *self = Entries::new(self.params);
+                    return Change::Reset;
+                }
+                Policy::Lru => {
+                    let victim = self.leaves.iter().find(|&&(_, index)| index != parent).cloned();
+                    let (_, index) = match victim {
+                        Some(victim) => victim,
+                        None => return Change::None,
+                    };
+                    let i = index as usize;
+                    self.leaves.remove(&(self.uses[i], index));
+                    let old_parent = self.parents[i] as usize;
+                    self.children[old_parent] -= 1;
+                    if old_parent != 0 && self.children[old_parent] == 0 {
+                        self.leaves.insert((self.uses[old_parent], old_parent as u64));
+                    }
+                    let evicted = (self.parents[i], self.bytes[i]);
+                    self.parents[i] = parent;
+                    self.bytes[i] = byte;
+                    (index, Some(evicted))
+                }
+            }
+        };
+
+        let p = parent as usize;
+        if self.params.policy == Policy::Lru && parent != 0 && self.children[p] == 0 {
+            self.leaves.remove(&(self.uses[p], parent));
+        }
+        self.children[p] += 1;
+        self.clock += 1;
+        self.uses[index as usize] = self.clock;
+        if self.params.policy == Policy::Lru {
+            self.leaves.insert((self.clock, index));
+        }
+        Change::Set(index, evicted)
+    }
+
+    /// Appends the bytes of word `pointer` to `output`.
+    fn expand(&self, mut pointer: u64, output: &mut Vec<u8>) {
+        let start = output.len();
+        while pointer != 0 {
+            output.push(self.bytes[pointer as usize]);
+            pointer = self.parents[pointer as usize];
+        }
+        output[start..].reverse();
+    }
+}
+
+pub fn lz78_coding<'a, I>(iter: I, params: Lz78Params) -> Vec<(u64, u8)>
     where I: Iterator<Item = &'a u8>
 {
-    let mut dictionnary: BTreeMap<Vec<u8>, u64> = BTreeMap::new();
+    assert!(params.is_valid(), "invalid LZ78 parameters {:?}", params);
+    let mut entries = Entries::new(params);
+    // edges of the trie: (word, next byte) -> longer word
+    let mut dictionnary: HashMap<(u64, u8), u64> = HashMap::new();
     let mut output: Vec<(u64, u8)> = Vec::new();
 
-    let mut word: Vec<u8> = Vec::new();
     let mut last_pointer: u64 = 0;
     for &item in iter {
-        word.push(item);
-        if let Some(&pointer) = dictionnary.get(&word) {
+        if let Some(&pointer) = dictionnary.get(&(last_pointer, item)) {
             last_pointer = pointer;
-        } else {
-            let index: u64 = (dictionnary.len() + 1) as u64;
-            dictionnary.insert(word, index);
-            output.push((last_pointer, item));
-            last_pointer = 0;
-            word = Vec::new();
+            continue;
         }
-
+        output.push((last_pointer, item));
+        entries.touch(last_pointer);
+        match entries.add(last_pointer, item) {
+            Change::None => {}
+            Change::Reset => dictionnary.clear(),
+            Change::Set(index, evicted) => {
+                if let Some(evicted) = evicted {
+                    dictionnary.remove(&evicted);
+                }
+                dictionnary.insert((last_pointer, item), index);
+            }
+        }
+        last_pointer = 0;
     }
-    if let Some(last_char) = word.pop() {
-        let pointer = if let Some(&pointer) = dictionnary.get(&word) {
-            pointer
-        } else {
-            0
-        };
-        output.push((pointer, last_char));
+    // the input ended inside a known word
+    if last_pointer != 0 {
+        let i = last_pointer as usize;
+        output.push((entries.parents[i], entries.bytes[i]));
     }
     output
 }
 
-#[allow(dead_code)]
-pub fn lz78_decoding<'a, I>(iter: I) -> Result<Vec<u8>, &'static str>
+pub fn lz78_decoding<'a, I>(iter: I, params: Lz78Params) -> Result<Vec<u8>, Error>
     where I: Iterator<Item = &'a (u64, u8)>
 {
-    let mut dictionnary: BTreeMap<u64, Vec<u8>> = BTreeMap::new();
-    dictionnary.insert(0, Vec::new());
+    assert!(params.is_valid(), "invalid LZ78 parameters {:?}", params);
+    let mut entries = Entries::new(params);
     let mut output: Vec<u8> = Vec::new();
 
-    for &(pointer, character) in iter {
-        let mut word = match dictionnary.get(&pointer) {
-            Some(tmp_word) => tmp_word.clone(),
-            None => return Err("Invalid entry"),
-        };
-
-        word.push(character);
-        let index = dictionnary.len();
-        dictionnary.insert(index as u64, word.clone());
-        output.append(&mut word);
-
-
+    for (index, &(pointer, character)) in iter.enumerate() {
+        if pointer >= entries.len() as u64 {
+            return Err(Error::InvalidBackReference { offset: Offset::Token(index) });
+        }
+        entries.expand(pointer, &mut output);
+        output.push(character);
+        entries.touch(pointer);
+        entries.add(pointer, character);
     }
     Ok(output)
 }
@@ -63,10 +239,37 @@ fn lz_78_testing() {
                               5, 4, 3, 6, 5, 4, 3, 2, 3, 4, 4, 4, 4, 4, 5, 6, 7, 8, 4, 9, 9, 9, 9,
                               9, 9, 9, 9, 9, 9, 9, 9, 7];
     println!("Input {:?}", input);
-    let coded = lz78_coding(input.iter());
+    let coded = lz78_coding(input.iter(), Lz78Params::default());
     println!("Coded {:?}", coded);
-    let decoded = lz78_decoding(coded.iter());
+    let decoded = lz78_decoding(coded.iter(), Lz78Params::default());
 
     println!("Decoded {:?}", decoded);
     assert_eq!(input, decoded.unwrap());
+
+    assert_eq!(lz78_decoding([(0, 1), (2, 1)].iter(), Lz78Params::default()),
+               Err(Error::InvalidBackReference { offset: Offset::Token(1) }));
+
+    // small dictionaries fill up many times, and both sides must agree on every change
+    let mut input = Vec::new();
+    let mut x: u32 = 0;
+    for _ in 0..20000 {
+        x = x.wrapping_mul(22695477).wrapping_add(1);
+        input.push((x >> 16) as u8 % 5);
+    }
+    for &policy in &[Policy::Freeze, Policy::Reset, Policy::Lru] {
+        for &max_entries in &[1, 2, 7, 100] {
+            let params = Lz78Params::new(max_entries, policy);
+            let coded = lz78_coding(input.iter(), params);
+            assert!(coded.iter().all(|&(pointer, _)| pointer <= max_entries as u64));
+            assert_eq!(input, lz78_decoding(coded.iter(), params).unwrap());
+        }
+    }
+
+    // with LRU, index 3 goes from [1, 1] to [2, 2] then to [1, 2]: words 1 and 2 are prefixes
+    // of the new words, so they are not evicted
+    let params = Lz78Params::new(3, Policy::Lru);
+    let input = vec![1, 2, 1, 1, 2, 2, 1, 2, 1, 2, 3];
+    let coded = lz78_coding(input.iter(), params);
+    assert_eq!(coded, vec![(0, 1), (0, 2), (1, 1), (2, 2), (1, 2), (3, 3)]);
+    assert_eq!(input, lz78_decoding(coded.iter(), params).unwrap());
 }
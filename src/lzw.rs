@@ -0,0 +1,194 @@
+//! LZW: LZ78 with a dictionary seeded with the 256 bytes, so that only codes are sent.
+//!
+//! Codes start 9 bits wide and grow up to `max_bits`. Once the dictionary is full it is
+//! either frozen or, with `clear`, reset after the clear code 256.
+
+use bit_vec::BitVec;
+use bitvec_util::*;
+use error::{Error, Offset};
+use std::collections::HashMap;
+
+pub const MIN_BITS: u32 = 9;
+pub const MAX_BITS: u32 = 24;
+const CLEAR: u32 = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LzwParams {
+    pub max_bits: u32,
+    pub clear: bool,
+}
+
+impl Default for LzwParams {
+    fn default() -> LzwParams {
+        LzwParams {
+            max_bits: 16,
+            clear: true,
+        }
+    }
+}
+
+impl LzwParams {
+    pub fn new(max_bits: u32, clear: bool) -> LzwParams {
+        let params = LzwParams { max_bits, clear };
+        assert!(params.is_valid(), "invalid LZW parameters {:?}", params);
+        params
+    }
+
+    pub fn is_valid(&self) -> bool {
+        self.max_bits >= MIN_BITS && self.max_bits <= MAX_BITS
+    }
+
+    /// First code given to a dictionary entry.
+    fn first_code(&self) -> u32 {
+        if self.clear { CLEAR + 1 } else { CLEAR }
+    }
+
+    /// Width of the code sent after `emitted` others since the last reset. The decoder is one
+    /// entry behind the encoder, so this code is at most `first_code + emitted - 1`.
+    fn width(&self, emitted: u32) -> u32 {
+        let max_code = self.first_code().saturating_add(emitted).saturating_sub(1);
+        (32 - max_code.leading_zeros()).clamp(MIN_BITS, self.max_bits)
+    }
+}
+
+pub fn lzw_coding<I>(iter: I, params: LzwParams) -> BitVec
+    where I: Iterator<Item = u8>
+{
+    assert!(params.is_valid(), "invalid LZW parameters {:?}", params);
+    let limit = 1 << params.max_bits;
+    let mut dictionnary: HashMap<(u32, u8), u32> = HashMap::new();
+    let mut next_code = params.first_code();
+    let mut emitted = 0;
+    let mut output = BitVec::new();
+
+    let mut word: Option<u32> = None;
+    for byte in iter {
+        let current = match word {
+            None => {
+                word = Some(byte as u32);
+                continue;
+            }
+            Some(current) => current,
+        };
+        if let Some(&code) = dictionnary.get(&(current, byte)) {
+            word = Some(code);
+            continue;
+        }
+
+        push_bits(&mut output, current as u64, params.width(emitted));
+        emitted = emitted.saturating_add(1);
+        if next_code < limit {
+            dictionnary.insert((current, byte), next_code);
+            next_code += 1;
+        } else if params.clear {
+            push_bits(&mut output, CLEAR as u64, params.width(emitted));
+            dictionnary.clear();
+            next_code = params.first_code();
+            emitted = 0;
+        }
+        word = Some(byte as u32);
+    }
+    if let Some(current) = word {
+        push_bits(&mut output, current as u64, params.width(emitted));
+    }
+    output
+}
+
+pub fn lzw_decoding(iter: &mut BitReader, params: LzwParams) -> Result<Vec<u8>, Error> {
+    assert!(params.is_valid(), "invalid LZW parameters {:?}", params);
+    let limit = 1 << params.max_bits;
+    // entries from `first_code` on, as (prefix code, last byte)
+    let mut entries: Vec<(u32, u8)> = Vec::new();
+    let mut emitted = 0;
+    let mut previous: Option<u32> = None;
+    let mut output = Vec::new();
+    let mut word = Vec::new();
+
+    while iter.remaining() > 0 {
+        let position = iter.offset();
+        let code = read_bits(iter, params.width(emitted))? as u32;
+        emitted = emitted.saturating_add(1);
+
+        if params.clear && code == CLEAR {
+            if previous.is_none() {
+                return Err(Error::InvalidBackReference { offset: Offset::Bit(position) });
+            }
+            entries.clear();
+            emitted = 0;
+            previous = None;
+            continue;
+        }
+
+        let next_code = params.first_code() + entries.len() as u32;
+        let known = code < CLEAR || (code >= params.first_code() && code < next_code);
+        // the code defined by this very step, whose word starts like the previous one
+        let pending = previous.is_some() && code == next_code && next_code < limit;
+        if !known && !pending {
+            return Err(Error::InvalidBackReference { offset: Offset::Bit(position) });
+        }
+
+        if pending {
+            expand(previous.unwrap(), &entries, params, &mut word);
+            let first = word[0];
+            word.push(first);
+        } else {
+            expand(code, &entries, params, &mut word);
+        }
+        if let Some(previous) = previous {
+            if next_code < limit {
+                entries.push((previous, word[0]));
+            }
+        }
+        output.extend_from_slice(&word);
+        previous = Some(code);
+    }
+    Ok(output)
+}
+
+/// Replaces `word` with the bytes of `code`.
+fn expand(mut code: u32, entries: &[(u32, u8)], params: LzwParams, word: &mut Vec<u8>) {
+    word.clear();
+    while code >= params.first_code() {
+        let (prefix, byte) = entries[(code - params.first_code()) as usize];
+        word.push(byte);
+        code = prefix;
+    }
+    word.push(code as u8);
+    word.reverse();
+}
+
+#[test]
+fn lzw_testing() {
+    let input = "TOBEORNOTTOBEORTOBEORNOT".as_bytes();
+    let coded = lzw_coding(input.iter().cloned(), LzwParams::new(9, false));
+    // 16 codes of 9 bits, as in the classic example
+    assert_eq!(coded.len(), 16 * 9);
+    let decoded = lzw_decoding(&mut BitReader::new(&coded), LzwParams::new(9, false)).unwrap();
+    assert_eq!(input, &decoded[..]);
+
+    // small dictionaries fill up quickly, and the widths must agree on both sides
+    let mut input = Vec::new();
+    let mut x: u32 = 0;
+    for _ in 0..30000 {
+        x = x.wrapping_mul(22695477).wrapping_add(1);
+        input.push((x >> 16) as u8 % 11);
+    }
+    input.extend(vec![b'a'; 5000]);
+    for &params in &[LzwParams::new(9, false), LzwParams::new(9, true), LzwParams::new(12, true),
+                     LzwParams::default()] {
+        let coded = lzw_coding(input.iter().cloned(), params);
+        let decoded = lzw_decoding(&mut BitReader::new(&coded), params).unwrap();
+        assert_eq!(input, decoded);
+    }
+    // a frozen dictionary keeps the widest codes however many have been sent
+    for &params in &[LzwParams::new(9, false), LzwParams::default()] {
+        assert_eq!(params.width(u32::MAX), params.max_bits);
+    }
+
+    // a code that is not defined yet
+    let mut coded = BitVec::new();
+    push_bits(&mut coded, b'a' as u64, 9);
+    push_bits(&mut coded, 300, 9);
+    assert_eq!(lzw_decoding(&mut BitReader::new(&coded), LzwParams::default()),
+               Err(Error::InvalidBackReference { offset: Offset::Bit(9) }));
+}
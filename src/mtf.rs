@@ -0,0 +1,113 @@
+//! Move-to-front: each symbol is replaced by its index in a list of the alphabet, then moved to
+//! the front of the list, so recently seen symbols get small indexes.
+
+use std::borrow::Borrow;
+
+pub struct MoveToFront<T> {
+    order: Vec<T>,
+}
+
+impl<T: PartialEq + Copy> MoveToFront<T> {
+    /// The alphabet in its initial order.
+    pub fn new(alphabet: Vec<T>) -> MoveToFront<T> {
+        MoveToFront { order: alphabet }
+    }
+
+    /// Index of `symbol`, or `None` if it is not in the alphabet.
+    pub fn encode(&mut self, symbol: T) -> Option<usize> {
+        let index = self.order.iter().position(|&s| s == symbol)?;
+        self.order[..index + 1].rotate_right(1);
+        Some(index)
+    }
+
+    /// Symbol at `index`, or `None` if the alphabet is smaller.
+    pub fn decode(&mut self, index: usize) -> Option<T> {
+        let symbol = *self.order.get(index)?;
+        self.order[..index + 1].rotate_right(1);
+        Some(symbol)
+    }
+}
+
+impl MoveToFront<u8> {
+    /// The 256 bytes in increasing order.
+    pub fn bytes() -> MoveToFront<u8> {
+        MoveToFront::new((0..=255).collect())
+    }
+}
+
+pub struct MtfCodingIter<I> {
+    iter: I,
+    mtf: MoveToFront<u8>,
+}
+
+impl<I> Iterator for MtfCodingIter<I>
+    where I: Iterator,
+          I::Item: Borrow<u8>
+{
+    type Item = u8;
+    fn next(&mut self) -> Option<u8> {
+        let byte = *self.iter.next()?.borrow();
+        self.mtf.encode(byte).map(|index| index as u8)
+    }
+}
+
+pub fn mtf_coding<I>(iter: I) -> MtfCodingIter<I>
+    where I: Iterator,
+          I::Item: Borrow<u8>
+{
+    MtfCodingIter {
+        iter,
+        mtf: MoveToFront::bytes(),
+    }
+}
+
+pub struct MtfDecodingIter<I> {
+    iter: I,
+    mtf: MoveToFront<u8>,
+}
+
+impl<I> Iterator for MtfDecodingIter<I>
+    where I: Iterator,
+          I::Item: Borrow<u8>
+{
+    type Item = u8;
+    fn next(&mut self) -> Option<u8> {
+        let index = *self.iter.next()?.borrow();
+        self.mtf.decode(index as usize)
+    }
+}
+
+pub fn mtf_decoding<I>(iter: I) -> MtfDecodingIter<I>
+    where I: Iterator,
+          I::Item: Borrow<u8>
+{
+    MtfDecodingIter {
+        iter,
+        mtf: MoveToFront::bytes(),
+    }
+}
+
+pub fn mtf_encode(content: &[u8]) -> Vec<u8> {
+    mtf_coding(content.iter()).collect()
+}
+
+pub fn mtf_decode(content: &[u8]) -> Vec<u8> {
+    mtf_decoding(content.iter()).collect()
+}
+
+#[test]
+fn mtf_testing() {
+    assert_eq!(mtf_encode(b"bananaaa"), vec![98, 98, 110, 1, 1, 1, 0, 0]);
+    assert_eq!(mtf_decode(&mtf_encode(b"bananaaa")), b"bananaaa".to_vec());
+    let input: Vec<u8> = (0..=255).rev().chain(0..=255).collect();
+    assert_eq!(mtf_decoding(mtf_coding(input.iter())).collect::<Vec<u8>>(), input);
+
+    // any alphabet, with symbols outside of it reported
+    let mut mtf = MoveToFront::new(vec!['a', 'b', 'n']);
+    let indexes: Vec<Option<usize>> = "banana!".chars().map(|c| mtf.encode(c)).collect();
+    assert_eq!(indexes, vec![Some(1), Some(1), Some(2), Some(1), Some(1), Some(1), None]);
+    let mut mtf = MoveToFront::new(vec!['a', 'b', 'n']);
+    let symbols: Vec<Option<char>> = [1, 1, 2, 1, 1, 1, 3].iter().map(|&i| mtf.decode(i)).collect();
+    assert_eq!(symbols.iter().flatten().collect::<String>(), "banana");
+    assert_eq!(symbols[6], None);
+}
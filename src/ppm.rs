@@ -0,0 +1,300 @@
+//! Prediction by partial matching: each byte is coded by the longest context (up to `order`
+//! preceding bytes) that has seen it, escaping to shorter contexts otherwise (PPMC: the escape
+//! count is the number of distinct bytes of the context). Bytes already ruled out by a longer
+//! context are excluded from the shorter ones. Below order 0, all bytes are equally likely.
+
+use arithmetic::{ArithmeticDecoder, ArithmeticEncoder, Model};
+use bit_vec::BitVec;
+use bitvec_util::*;
+use error::{Error, Offset};
+use std::collections::HashMap;
+
+pub const MAX_ORDER: usize = 15;
+const ESCAPE: u16 = 256;
+/// Counts of a context are halved beyond this total.
+const MAX_COUNT: u32 = 1 << 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PpmParams {
+    pub order: usize,
+    /// The model is emptied when it holds more (context, byte) counts than this.
+    pub max_nodes: usize,
+}
+
+impl Default for PpmParams {
+    fn default() -> PpmParams {
+        PpmParams {
+            order: 4,
+            max_nodes: 1 << 22,
+        }
+    }
+}
+
+impl PpmParams {
+    pub fn new(order: usize, max_nodes: usize) -> PpmParams {
+        let params = PpmParams { order, max_nodes };
+        assert!(params.is_valid(), "invalid PPM parameters {:?}", params);
+        params
+    }
+
+    pub fn is_valid(&self) -> bool {
+        self.order <= MAX_ORDER && self.max_nodes >= 1
+    }
+}
+
+#[derive(Default)]
+struct Context {
+    counts: Vec<(u8, u32)>,
+    total: u32,
+}
+
+/// The distribution coded at one order: the bytes not excluded, then the escape if any.
+struct Step {
+    symbols: Vec<u16>,
+    cumulative: Vec<u32>,
+}
+
+impl Step {
+    fn new(context: Option<&Context>, excluded: &[bool; 256]) -> Step {
+        let mut step = Step {
+            symbols: Vec::new(),
+            cumulative: vec![0],
+        };
+        match context {
+            Some(context) => {
+                for &(byte, count) in &context.counts {
+                    if !excluded[byte as usize] {
+                        step.push(byte as u16, count);
+                    }
+                }
+                if !step.symbols.is_empty() {
+                    step.push(ESCAPE, context.counts.len() as u32);
+                }
+            }
+            None => {
+                for (byte, &excluded) in excluded.iter().enumerate() {
+                    if !excluded {
+                        step.push(byte as u16, 1);
+                    }
+                }
+            }
+        }
+        step
+    }
+
+    fn push(&mut self, symbol: u16, count: u32) {
+        let total = self.total();
+        self.symbols.push(symbol);
+        self.cumulative.push(total + count);
+    }
+
+    /// Marks the bytes of this step as ruled out for the next orders.
+    fn exclude(&self, excluded: &mut [bool; 256]) {
+        for &symbol in &self.symbols {
+            if symbol != ESCAPE {
+                excluded[symbol as usize] = true;
+            }
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.symbols.is_empty()
+    }
+}
+
+impl Model<u16> for Step {
+    fn total(&self) -> u32 {
+        self.cumulative[self.symbols.len()]
+    }
+
+    fn interval(&self, symbol: u16) -> (u32, u32) {
+        let i = self.symbols.iter().position(|&s| s == symbol).unwrap();
+        (self.cumulative[i], self.cumulative[i + 1])
+    }
+
+    fn symbol(&self, target: u32) -> Option<(u16, u32, u32)> {
+        if target >= self.total() {
+            return None;
+        }
+        let i = self.cumulative.partition_point(|&low| low <= target) - 1;
+        Some((self.symbols[i], self.cumulative[i], self.cumulative[i + 1]))
+    }
+
+    fn update(&mut self, _: u16) {}
+}
+
+struct Ppm {
+    params: PpmParams,
+    contexts: HashMap<u128, Context>,
+    nodes: usize,
+    history: Vec<u8>,
+}
+
+impl Ppm {
+    fn new(params: PpmParams) -> Ppm {
+        assert!(params.is_valid(), "invalid PPM parameters {:?}", params);
+        Ppm {
+            params,
+            contexts: HashMap::new(),
+            nodes: 0,
+            history: Vec::new(),
+        }
+    }
+
+    /// The last `order` bytes and their count, as a map key.
+    fn key(&self, order: usize) -> u128 {
+        let start = self.history.len() - order;
+        self.history[start..].iter().fold(order as u128, |key, &byte| key << 8 | byte as u128)
+    }
+
+    /// The orders to try for the next byte, longest first.
+    fn orders(&self) -> ::std::iter::Rev<::std::ops::Range<usize>> {
+        (0..self.params.order.min(self.history.len()) + 1).rev()
+    }
+
+    fn update(&mut self, byte: u8) {
+        for order in self.orders() {
+            let key = self.key(order);
+            let context = self.contexts.entry(key).or_default();
+            match context.counts.iter().position(|&(b, _)| b == byte) {
+                Some(i) => context.counts[i].1 += 1,
+                None => {
+                    context.counts.push((byte, 1));
+                    self.nodes += 1;
+                }
+            }
+            context.total += 1;
+            if context.total > MAX_COUNT {
+                context.total = 0;
+                for count in &mut context.counts {
+                    count.1 = count.1.div_ceil(2);
+                    context.total += count.1;
+                }
+            }
+        }
+
+        if self.nodes > self.params.max_nodes {
+            self.contexts.clear();
+            self.nodes = 0;
+        }
+        self.history.push(byte);
+        if self.history.len() > 2 * MAX_ORDER {
+            self.history.drain(..MAX_ORDER);
+        }
+    }
+}
+
+pub fn ppm_coding<I>(iter: I, params: PpmParams) -> BitVec
+    where I: Iterator<Item = u8>
+{
+    let mut ppm = Ppm::new(params);
+    let mut encoder = ArithmeticEncoder::new();
+    for byte in iter {
+        let mut excluded = [false; 256];
+        let mut coded = false;
+        for order in ppm.orders() {
+            let mut step = Step::new(ppm.contexts.get(&ppm.key(order)), &excluded);
+            if step.is_empty() {
+                continue;
+            }
+            if step.symbols.contains(&(byte as u16)) {
+                encoder.encode(&mut step, byte as u16);
+                coded = true;
+                break;
+            }
+            encoder.encode(&mut step, ESCAPE);
+            step.exclude(&mut excluded);
+        }
+        if !coded {
+            encoder.encode(&mut Step::new(None, &excluded), byte as u16);
+        }
+        ppm.update(byte);
+    }
+    encoder.finish()
+}
+
+pub fn ppm_decoding(iter: &mut BitReader, params: PpmParams, count: u64)
+                    -> Result<Vec<u8>, Error> {
+    // each byte takes at least one choice among counts summing to at most `MAX_COUNT + 256`,
+    // which costs some code; past the end, the decoder stops where the encoder's bits do
+    let bits = iter.remaining() as u64 + 32;
+    if count > bits.saturating_mul(2 * (MAX_COUNT as u64 + 256)) {
+        return Err(Error::InvalidHeader { offset: Offset::Bit(iter.offset()) });
+    }
+    let mut ppm = Ppm::new(params);
+    let mut decoder = ArithmeticDecoder::new(iter);
+    let mut output = Vec::new();
+    for _ in 0..count {
+        let mut excluded = [false; 256];
+        let mut decoded = None;
+        for order in ppm.orders() {
+            let mut step = Step::new(ppm.contexts.get(&ppm.key(order)), &excluded);
+            if step.is_empty() {
+                continue;
+            }
+            let symbol = decoder.decode(&mut step, iter)?;
+            if symbol != ESCAPE {
+                decoded = Some(symbol as u8);
+                break;
+            }
+            step.exclude(&mut excluded);
+        }
+        let byte = match decoded {
+            Some(byte) => byte,
+            None => {
+                // only a corrupt stream escapes from every byte
+                let mut step = Step::new(None, &excluded);
+                if step.is_empty() {
+                    return Err(Error::InvalidHeader { offset: Offset::Bit(iter.offset()) });
+                }
+                decoder.decode(&mut step, iter)? as u8
+            }
+        };
+        ppm.update(byte);
+        output.push(byte);
+    }
+    Ok(output)
+}
+
+#[test]
+fn ppm_testing() {
+    let text = "the quick brown fox jumps over the lazy dog; ".repeat(40).into_bytes();
+    let mut input = text.clone();
+    input.extend(0..=255);
+    for &params in &[PpmParams::new(0, 1 << 20), PpmParams::new(3, 1 << 20),
+                     PpmParams::new(MAX_ORDER, 1 << 20), PpmParams::new(4, 100)] {
+        let coded = ppm_coding(input.iter().cloned(), params);
+        let decoded = ppm_decoding(&mut BitReader::new(&coded), params, input.len() as u64);
+        assert_eq!(input, decoded.unwrap());
+    }
+
+    // corrupt streams are rejected, without panicking: flipped bits can escape from every byte
+    let params = PpmParams::default();
+    let coded = ppm_coding(input.iter().cloned(), params);
+    let mut errors = 0;
+    for i in (0..coded.len()).step_by(11) {
+        let mut corrupt = coded.clone();
+        let bit = corrupt[i];
+        corrupt.set(i, !bit);
+        match ppm_decoding(&mut BitReader::new(&corrupt), params, input.len() as u64) {
+            Err(Error::InvalidHeader { .. }) | Err(Error::UnexpectedEof { .. }) => errors += 1,
+            Err(err) => panic!("unexpected error {:?}", err),
+            Ok(decoded) => assert_ne!(decoded, input),
+        }
+    }
+    assert!(errors > 0);
+
+    // a larger count than the stream's fails a few bytes past the end, or right away when no
+    // code of this length could hold it
+    let coded = ppm_coding(text.iter().cloned(), params);
+    let count = text.len() as u64 + 100;
+    assert_eq!(ppm_decoding(&mut BitReader::new(&coded), params, count),
+               Err(Error::UnexpectedEof { offset: Offset::Bit(coded.len()) }));
+    assert_eq!(ppm_decoding(&mut BitReader::new(&coded), params, 1 << 40),
+               Err(Error::InvalidHeader { offset: Offset::Bit(0) }));
+
+    // higher orders predict the repeated text much better
+    let order_0 = ppm_coding(text.iter().cloned(), PpmParams::new(0, 1 << 20)).len();
+    let order_3 = ppm_coding(text.iter().cloned(), PpmParams::new(3, 1 << 20)).len();
+    assert!(order_3 * 5 < order_0);
+}
@@ -0,0 +1,247 @@
+//! Run-length encodings: PackBits (as in TIFF), runs introduced by an escape byte, and the
+//! zero-run coding of bzip2 (RUNA/RUNB) for the output of move-to-front.
+
+use error::{Error, Offset};
+
+/// Shortest run worth a token in `escape_rle_encode`.
+const MIN_RUN: usize = 4;
+
+/// Zero runs are written in bijective base 2, with digits RUNA (1) and RUNB (2).
+pub const RUNA: u16 = 0;
+pub const RUNB: u16 = 1;
+
+/// Control byte `n < 128` is followed by `n + 1` literal bytes; `n > 128` by a byte repeated
+/// `257 - n` times.
+pub fn packbits_encode(content: &[u8]) -> Vec<u8> {
+    let mut output = Vec::new();
+    let mut literals = 0;
+    let mut i = 0;
+    while i < content.len() {
+        let mut run = 1;
+        while run < 128 && i + run < content.len() && content[i + run] == content[i] {
+            run += 1;
+        }
+        // a run of 2 only pays off between other runs
+        if run >= 3 || (run == 2 && literals == 0) {
+            output.push((257 - run) as u8);
+            output.push(content[i]);
+            i += run;
+            literals = 0;
+            continue;
+        }
+        if literals == 0 || literals == 128 {
+            output.push(0);
+            literals = 0;
+        }
+        let control = output.len() - literals - 1;
+        output[control] = literals as u8;
+        output.push(content[i]);
+        literals += 1;
+        i += 1;
+    }
+    output
+}
+
+pub fn packbits_decode(content: &[u8]) -> Result<Vec<u8>, Error> {
+    let mut output = Vec::new();
+    let mut position = 0;
+    while position < content.len() {
+        let control = content[position] as usize;
+        position += 1;
+        let needed = if control < 128 { control + 1 } else { (control > 128) as usize };
+        if position + needed > content.len() {
+            return Err(Error::UnexpectedEof { offset: Offset::Byte(content.len()) });
+        }
+        if control < 128 {
+            output.extend_from_slice(&content[position..position + needed]);
+        } else if control > 128 {
+            output.extend(std::iter::repeat_n(content[position], 257 - control));
+        }
+        position += needed;
+    }
+    Ok(output)
+}
+
+/// The output starts with the escape, the least frequent byte. Runs of at least `MIN_RUN`
+/// bytes, and the escape itself, are written as the escape, the length (up to 255) and the
+/// byte.
+pub fn escape_rle_encode(content: &[u8]) -> Vec<u8> {
+    if content.is_empty() {
+        return Vec::new();
+    }
+    let mut counts = [0usize; 256];
+    for &byte in content {
+        counts[byte as usize] += 1;
+    }
+    let escape = (0..=255u8).min_by_key(|&byte| counts[byte as usize]).unwrap();
+
+    let mut output = vec![escape];
+    let mut i = 0;
+    while i < content.len() {
+        let byte = content[i];
+        let mut run = 1;
+        while run < 255 && i + run < content.len() && content[i + run] == byte {
+            run += 1;
+        }
+        if run >= MIN_RUN || byte == escape {
+            output.extend_from_slice(&[escape, run as u8, byte]);
+        } else {
+            output.extend(std::iter::repeat_n(byte, run));
+        }
+        i += run;
+    }
+    output
+}
+
+pub fn escape_rle_decode(content: &[u8]) -> Result<Vec<u8>, Error> {
+    let escape = match content.first() {
+        Some(&escape) => escape,
+        None => return Ok(Vec::new()),
+    };
+    let mut output = Vec::new();
+    let mut position = 1;
+    while position < content.len() {
+        if content[position] != escape {
+            output.push(content[position]);
+            position += 1;
+            continue;
+        }
+        if position + 3 > content.len() {
+            return Err(Error::UnexpectedEof { offset: Offset::Byte(content.len()) });
+        }
+        if content[position + 1] == 0 {
+            return Err(Error::InvalidHeader { offset: Offset::Byte(position + 1) });
+        }
+        output.extend(std::iter::repeat_n(content[position + 2], content[position + 1] as usize));
+        position += 3;
+    }
+    Ok(output)
+}
+
+/// Runs of zeros as RUNA/RUNB digits, least significant first; any other value `v` becomes
+/// `v + 1`.
+pub fn zero_run_coding(values: &[u16]) -> Vec<u16> {
+    let mut symbols = Vec::new();
+    let mut run = 0;
+    for &value in values {
+        if value == 0 {
+            run += 1;
+            continue;
+        }
+        push_run(&mut symbols, run);
+        run = 0;
+        symbols.push(value + 1);
+    }
+    push_run(&mut symbols, run);
+    symbols
+}
+
+fn push_run(symbols: &mut Vec<u16>, mut run: usize) {
+    while run > 0 {
+        if run & 1 == 1 {
+            symbols.push(RUNA);
+            run = (run - 1) / 2;
+        } else {
+            symbols.push(RUNB);
+            run = (run - 2) / 2;
+        }
+    }
+}
+
+/// Undoes `zero_run_coding`, or returns `None` if the values would be more than `max_len`.
+pub fn zero_run_decoding(symbols: &[u16], max_len: usize) -> Option<Vec<u16>> {
+    let mut values = Vec::new();
+    let (mut run, mut weight) = (0usize, 1usize);
+    for &symbol in symbols {
+        if symbol == RUNA || symbol == RUNB {
+            // any further digit would exceed the bound
+            if weight > max_len {
+                return None;
+            }
+            run = run.checked_add(weight << (symbol == RUNB) as u32)?;
+            weight = weight.checked_mul(2)?;
+            if run > max_len {
+                return None;
+            }
+            continue;
+        }
+        if values.len() + run >= max_len {
+            return None;
+        }
+        values.extend(std::iter::repeat_n(0, run));
+        run = 0;
+        weight = 1;
+        values.push(symbol - 1);
+    }
+    if values.len() + run > max_len {
+        return None;
+    }
+    values.extend(std::iter::repeat_n(0, run));
+    Some(values)
+}
+
+/// `zero_run_coding` of bytes, as bytes: symbols 255 and 256 take a second byte.
+pub fn zero_run_encode(content: &[u8]) -> Vec<u8> {
+    let values: Vec<u16> = content.iter().map(|&byte| byte as u16).collect();
+    let mut output = Vec::new();
+    for symbol in zero_run_coding(&values) {
+        match symbol {
+            0..=254 => output.push(symbol as u8),
+            _ => output.extend_from_slice(&[255, (symbol - 255) as u8]),
+        }
+    }
+    output
+}
+
+/// Undoes `zero_run_encode`, or fails if the output would be more than `max_len` bytes.
+pub fn zero_run_decode(content: &[u8], max_len: usize) -> Result<Vec<u8>, Error> {
+    let mut symbols = Vec::new();
+    let mut position = 0;
+    while position < content.len() {
+        let mut symbol = content[position] as u16;
+        if symbol == 255 {
+            match content.get(position + 1) {
+                Some(&extra) if extra <= 1 => symbol += extra as u16,
+                Some(_) => return Err(Error::InvalidHeader { offset: Offset::Byte(position + 1) }),
+                None => return Err(Error::UnexpectedEof { offset: Offset::Byte(content.len()) }),
+            }
+            position += 1;
+        }
+        symbols.push(symbol);
+        position += 1;
+    }
+    let values = zero_run_decoding(&symbols, max_len)
+        .ok_or(Error::InvalidHeader { offset: Offset::Byte(0) })?;
+    Ok(values.into_iter().map(|value| value as u8).collect())
+}
+
+#[test]
+fn rle_testing() {
+    assert_eq!(packbits_encode(b"AAABBBBBCDE"), vec![254, b'A', 252, b'B', 2, b'C', b'D', b'E']);
+    assert_eq!(escape_rle_encode(&[7, 7, 7, 7, 7, 1, 0]), vec![2, 2, 5, 7, 1, 0]);
+    // runs of 1 to 6 zeros: A, B, AA, BA, AB, BB
+    assert_eq!(zero_run_coding(&[0, 0, 0, 0, 0, 0, 3]), vec![RUNB, RUNB, 4]);
+
+    let mut inputs = vec![vec![], vec![1], vec![0; 1000], vec![255; 300], (0..=255).collect()];
+    let mut input = Vec::new();
+    let mut x: u32 = 1;
+    for _ in 0..3000 {
+        x = x.wrapping_mul(22695477).wrapping_add(1);
+        let len = (x >> 16) as usize % 20;
+        input.extend(std::iter::repeat_n((x >> 24) as u8 % 4 * 85, len));
+    }
+    inputs.push(input);
+    for input in &inputs {
+        assert_eq!(input, &packbits_decode(&packbits_encode(input)).unwrap());
+        assert_eq!(input, &escape_rle_decode(&escape_rle_encode(input)).unwrap());
+        assert_eq!(input, &zero_run_decode(&zero_run_encode(input), input.len()).unwrap());
+    }
+
+    assert_eq!(packbits_decode(&[3, 1, 2]),
+               Err(Error::UnexpectedEof { offset: Offset::Byte(3) }));
+    assert_eq!(escape_rle_decode(&[9, 9, 0, 1]),
+               Err(Error::InvalidHeader { offset: Offset::Byte(2) }));
+    // a run of about 2^61 zeros is refused, not allocated
+    assert!(zero_run_decode(&[1; 60], 1 << 20).is_err());
+    assert!(zero_run_decode(&zero_run_encode(&[0; 100]), 99).is_err());
+}
@@ -0,0 +1,408 @@
+//! `Read`/`Write` adapters that compress with bounded memory.
+//!
+//! `Lz77Encoder`/`Lz77Decoder` stream token by token and produce exactly the
+//! `compression_lz77` format. Two-pass algorithms (Huffman, LZ78) cannot stream a single
+//! tree or dictionary, so `BlockEncoder`/`BlockDecoder` cut the input into blocks and
+//! compress each one independently with a codec:
+//!
+//! ```text
+//! (block length: u32 LE | compressed block)* | 0u32
+//! ```
+
+use super::Lz77Format;
+use codec::Codec;
+use lz_77::{lz77_coding, lz77_decoding, LZ77CodingIter, LZ77DecodingIter, Lz77Params};
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::rc::Rc;
+
+pub const DEFAULT_BLOCK_SIZE: usize = 1 << 20;
+
+/// Fills `buf` completely; returns `Ok(false)` if the reader was already at EOF.
+fn read_exact_or_eof<R: Read>(reader: &mut R, buf: &mut [u8]) -> io::Result<bool> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) if filled == 0 => return Ok(false),
+            Ok(0) => return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "truncated stream")),
+            Ok(n) => filled += n,
+            Err(ref err) if err.kind() == io::ErrorKind::Interrupted => {}
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(true)
+}
+
+/**************************************************************************************************
+ LZ77
+*/
+
+struct Pending(Rc<RefCell<VecDeque<u8>>>);
+
+impl Iterator for Pending {
+    type Item = u8;
+    fn next(&mut self) -> Option<u8> {
+        self.0.borrow_mut().pop_front()
+    }
+}
+
+pub struct Lz77Encoder<W: Write> {
+    writer: Option<W>,
+    format: Lz77Format,
+    header_written: bool,
+    pending: Rc<RefCell<VecDeque<u8>>>,
+    coder: LZ77CodingIter<Pending>,
+}
+
+impl<W: Write> Lz77Encoder<W> {
+    pub fn new(writer: W) -> Lz77Encoder<W> {
+        Lz77Encoder::with_params(writer, Lz77Params::default())
+    }
+
+    pub fn with_params(writer: W, params: Lz77Params) -> Lz77Encoder<W> {
+        let pending = Rc::new(RefCell::new(VecDeque::new()));
+        Lz77Encoder {
+            writer: Some(writer),
+            format: Lz77Format::new(params),
+            header_written: false,
+            coder: lz77_coding(Pending(pending.clone()), params),
+            pending,
+        }
+    }
+
+    /// Codes tokens as long as at least `keep` bytes are pending, so that the coder always
+    /// sees a full lookahead until the input is finished.
+    fn code(&mut self, keep: usize) -> io::Result<()> {
+        let mut output = Vec::new();
+        if !self.header_written {
+            self.format.write_header(&mut output);
+            self.header_written = true;
+        }
+        while self.pending.borrow().len() >= keep {
+            match self.coder.next() {
+                Some(triplet) => self.format.pack(triplet, &mut output),
+                None => break,
+            }
+        }
+        self.writer.as_mut().unwrap().write_all(&output)
+    }
+
+    /// Codes the remaining input and returns the underlying writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.code(0)?;
+        let mut writer = self.writer.take().unwrap();
+        writer.flush()?;
+        Ok(writer)
+    }
+}
+
+impl<W: Write> Write for Lz77Encoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.pending.borrow_mut().extend(buf);
+        let lookahead = self.format.params.lookahead();
+        self.code(lookahead)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.as_mut().unwrap().flush()
+    }
+}
+
+impl<W: Write> Drop for Lz77Encoder<W> {
+    fn drop(&mut self) {
+        if self.writer.is_some() {
+            let _ = self.code(0);
+        }
+    }
+}
+
+struct Triplets<R: Read> {
+    reader: R,
+    format: Lz77Format,
+    error: Option<io::Error>,
+}
+
+impl<R: Read> Iterator for Triplets<R> {
+    type Item = (usize, usize, u8);
+    fn next(&mut self) -> Option<(usize, usize, u8)> {
+        if self.error.is_some() {
+            return None;
+        }
+        let mut bytes = vec![0u8; self.format.token_size()];
+        match read_exact_or_eof(&mut self.reader, &mut bytes) {
+            Ok(true) => {
+                let (ptr, len, byte) = self.format.unpack(&bytes);
+                let params = self.format.params;
+                if (ptr == 0 && len > 0) || ptr >= params.window_size || len >= params.view_size {
+                    self.error = Some(io::Error::new(io::ErrorKind::InvalidData,
+                                                     "invalid back reference"));
+                    return None;
+                }
+                Some((ptr, len, byte))
+            }
+            Ok(false) => None,
+            Err(err) => {
+                self.error = Some(err);
+                None
+            }
+        }
+    }
+}
+
+pub struct Lz77Decoder<R: Read> {
+    reader: Option<R>,
+    decoder: Option<LZ77DecodingIter<Triplets<R>>>,
+}
+
+impl<R: Read> Lz77Decoder<R> {
+    pub fn new(reader: R) -> Lz77Decoder<R> {
+        Lz77Decoder {
+            reader: Some(reader),
+            decoder: None,
+        }
+    }
+
+    /// Reads the window and view sizes written by the encoder.
+    fn read_header(&mut self) -> io::Result<()> {
+        let mut reader = self.reader.take().unwrap();
+        let mut header = Vec::new();
+        let mut varints = 0;
+        // two varints, of at most 10 bytes each
+        while varints < 2 && header.len() < 20 {
+            let mut byte = [0u8];
+            if !read_exact_or_eof(&mut reader, &mut byte)? {
+                return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "truncated header"));
+            }
+            header.push(byte[0]);
+            if byte[0] & 0x80 == 0 {
+                varints += 1;
+            }
+        }
+        let format = Lz77Format::read_header(&header, &mut 0)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+
+        let params = format.params;
+        self.decoder = Some(lz77_decoding(Triplets {
+                                              reader,
+                                              format,
+                                              error: None,
+                                          },
+                                          params));
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for Lz77Decoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.decoder.is_none() {
+            self.read_header()?;
+        }
+        let decoder = self.decoder.as_mut().unwrap();
+        let mut n = 0;
+        while n < buf.len() {
+            match decoder.next() {
+                Some(byte) => {
+                    buf[n] = byte;
+                    n += 1;
+                }
+                None => break,
+            }
+        }
+        if n == 0 {
+            if let Some(err) = decoder.get_mut().error.take() {
+                return Err(err);
+            }
+        }
+        Ok(n)
+    }
+}
+
+/**************************************************************************************************
+ blocks
+*/
+
+pub struct BlockEncoder<W: Write, C: Codec> {
+    writer: Option<W>,
+    codec: C,
+    block: Vec<u8>,
+    block_size: usize,
+}
+
+impl<W: Write, C: Codec> BlockEncoder<W, C> {
+    pub fn new(writer: W, codec: C) -> BlockEncoder<W, C> {
+        BlockEncoder::with_block_size(writer, codec, DEFAULT_BLOCK_SIZE)
+    }
+
+    pub fn with_block_size(writer: W, codec: C, block_size: usize) -> BlockEncoder<W, C> {
+        assert!(block_size > 0, "block size must be positive");
+        BlockEncoder {
+            writer: Some(writer),
+            codec,
+            block: Vec::with_capacity(block_size),
+            block_size,
+        }
+    }
+
+    fn write_block(&mut self) -> io::Result<()> {
+        if self.block.is_empty() {
+            return Ok(());
+        }
+        let data = self.codec.encode(&self.block);
+        if data.len() > u32::MAX as usize {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "compressed block too large"));
+        }
+        self.block.clear();
+
+        let writer = self.writer.as_mut().unwrap();
+        let len = data.len() as u32;
+        writer.write_all(&[len as u8, (len >> 8) as u8, (len >> 16) as u8, (len >> 24) as u8])?;
+        writer.write_all(&data)
+    }
+
+    fn write_end(&mut self) -> io::Result<()> {
+        self.write_block()?;
+        self.writer.as_mut().unwrap().write_all(&[0; 4])
+    }
+
+    /// Writes the last block and the end marker, and returns the underlying writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.write_end()?;
+        let mut writer = self.writer.take().unwrap();
+        writer.flush()?;
+        Ok(writer)
+    }
+}
+
+impl<W: Write, C: Codec> Write for BlockEncoder<W, C> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut rest = buf;
+        while !rest.is_empty() {
+            let n = (self.block_size - self.block.len()).min(rest.len());
+            self.block.extend_from_slice(&rest[..n]);
+            rest = &rest[n..];
+            if self.block.len() == self.block_size {
+                self.write_block()?;
+            }
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.as_mut().unwrap().flush()
+    }
+}
+
+impl<W: Write, C: Codec> Drop for BlockEncoder<W, C> {
+    fn drop(&mut self) {
+        if self.writer.is_some() {
+            let _ = self.write_end();
+        }
+    }
+}
+
+pub struct BlockDecoder<R: Read, C: Codec> {
+    reader: R,
+    codec: C,
+    block: Vec<u8>,
+    position: usize,
+    done: bool,
+}
+
+impl<R: Read, C: Codec> BlockDecoder<R, C> {
+    pub fn new(reader: R, codec: C) -> BlockDecoder<R, C> {
+        BlockDecoder {
+            reader,
+            codec,
+            block: Vec::new(),
+            position: 0,
+            done: false,
+        }
+    }
+
+    fn read_block(&mut self) -> io::Result<()> {
+        let mut len = [0u8; 4];
+        if !read_exact_or_eof(&mut self.reader, &mut len)? {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "missing end of stream"));
+        }
+        let len = len.iter().rev().fold(0usize, |len, &byte| len << 8 | byte as usize);
+        if len == 0 {
+            self.done = true;
+            return Ok(());
+        }
+
+        // the length is not trusted: the buffer only grows with the data actually read
+        let mut data = Vec::new();
+        (&mut self.reader).take(len as u64).read_to_end(&mut data)?;
+        if data.len() < len {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "truncated block"));
+        }
+        self.block = self.codec.decode(&data)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        self.position = 0;
+        Ok(())
+    }
+}
+
+impl<R: Read, C: Codec> Read for BlockDecoder<R, C> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.position == self.block.len() && !self.done {
+            self.read_block()?;
+        }
+        let n = (self.block.len() - self.position).min(buf.len());
+        buf[..n].copy_from_slice(&self.block[self.position..self.position + n]);
+        self.position += n;
+        Ok(n)
+    }
+}
+
+#[test]
+fn stream_testing() {
+    let mut input = Vec::new();
+    let mut x: u32 = 0;
+    for _ in 0..20000 {
+        x = x.wrapping_mul(22695477).wrapping_add(1);
+        input.push((x >> 16) as u8 % 7);
+    }
+
+    let lazy = Lz77Params { parse: ::Parse::Lazy, ..Lz77Params::default() };
+    let optimal = Lz77Params { parse: ::Parse::Optimal, ..Lz77Params::new(1 << 16, 100) };
+    for &params in &[Lz77Params::default(), Lz77Params::new(1 << 16, 100), lazy, optimal] {
+        let mut encoder = Lz77Encoder::with_params(Vec::new(), params);
+        for chunk in input.chunks(1000) {
+            encoder.write_all(chunk).unwrap();
+        }
+        let coded = encoder.finish().unwrap();
+        assert_eq!(coded, ::compression_lz77_with(input.iter().cloned(), params));
+
+        let mut decoded = Vec::new();
+        Lz77Decoder::new(&coded[..]).read_to_end(&mut decoded).unwrap();
+        assert_eq!(input, decoded);
+        let truncated = &coded[..coded.len() - 1];
+        assert!(Lz77Decoder::new(truncated).read_to_end(&mut Vec::new()).is_err());
+    }
+
+    let registry = ::Registry::default();
+    for names in &["huffman", "lz78", "lz77+huffman"] {
+        let chain = registry.chain(names).unwrap();
+        let mut encoder = BlockEncoder::with_block_size(Vec::new(), chain.clone(), 4096);
+        for chunk in input.chunks(1000) {
+            encoder.write_all(chunk).unwrap();
+        }
+        let coded = encoder.finish().unwrap();
+
+        let mut decoded = Vec::new();
+        BlockDecoder::new(&coded[..], chain.clone()).read_to_end(&mut decoded).unwrap();
+        assert_eq!(input, decoded);
+
+        let truncated = &coded[..coded.len() - 4];
+        assert!(BlockDecoder::new(truncated, chain).read_to_end(&mut Vec::new()).is_err());
+    }
+
+    // a 4 GiB block length in front of a few bytes fails without allocating it
+    let hostile = [0xff, 0xff, 0xff, 0xff, 1, 2, 3];
+    let mut decoder = BlockDecoder::new(&hostile[..], registry.chain("huffman").unwrap());
+    let err = decoder.read_to_end(&mut Vec::new()).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+}